@@ -0,0 +1,362 @@
+use std::{fmt::Display, sync::Arc};
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::Manager;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    entities::{AppState, UserSession, UserType},
+    events::{self, AppEvent},
+    requests::{get_online_list, get_user_dashboard, simulate_login, simulate_login_via_vpn},
+    session::VpnSession,
+    setting::account::{AccountPreferences, LoginMode, SavedAccount},
+    utils::webvpn::WebVpnProfile,
+};
+
+// 同时登录的账号数，太多了校园网后台会拒绝
+const MAX_CONCURRENT_LOGINS: usize = 4;
+
+// 多账号概览里的一行
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummary {
+    pub user_name: String,
+    pub left_money: Option<f64>, // 元
+    pub use_flow: Option<f64>,   // MB
+    pub left_flow: Option<f64>,  // MB
+    pub online_count: Option<usize>,
+    pub error: Option<String>, // 登录或查询失败的原因
+}
+
+impl AccountSummary {
+    fn parse(user_name: &str, dashboard: &str, online_list: &str) -> Result<Self> {
+        let dashboard: Value = serde_json::from_str(dashboard)?;
+        let online_list: Vec<Value> = serde_json::from_str(online_list)
+            .map_err(|_| anyhow!("获取在线设备失败，可能是登录已失效"))?;
+        Ok(AccountSummary {
+            user_name: user_name.to_string(),
+            left_money: dashboard["leftMoney"].as_f64(),
+            use_flow: dashboard["useFlow"].as_f64(),
+            left_flow: dashboard["leftFlow"].as_f64(),
+            online_count: Some(online_list.len()),
+            error: None,
+        })
+    }
+
+    fn failed(user_name: String, e: impl Display) -> Self {
+        AccountSummary {
+            user_name,
+            error: Some(e.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+// 只登录校园网后台拿到会话，不改变当前登录的账号
+async fn login(
+    user_name: &str,
+    password: &str,
+    user_type: &UserType,
+) -> Result<(UserSession, Option<String>)> {
+    let (cookie_str, dashboard) = match user_type {
+        UserType::Normal => simulate_login(user_name, password).await?,
+        UserType::ViaVpn(profile) => simulate_login_via_vpn(profile, user_name, password).await?,
+    };
+    let session = UserSession {
+        cookie_str: cookie_str.ok_or(anyhow!("用户名或密码错误！"))?,
+        user_type: user_type.clone(),
+    };
+    Ok((session, dashboard))
+}
+
+// 按账号设置的登录方式，via_vpn 为 true 时一律通过 WebVPN
+pub(crate) fn user_type_for(
+    account: &SavedAccount,
+    via_vpn: bool,
+    profile: &WebVpnProfile,
+) -> UserType {
+    if via_vpn || account.preferences.login_mode == LoginMode::Vpn {
+        UserType::ViaVpn(profile.clone())
+    } else {
+        UserType::Normal
+    }
+}
+
+// 之前的会话还能用就直接用，否则重新登录，返回会话和 dashboard
+pub(crate) async fn ensure_session(
+    user_name: &str,
+    password: &str,
+    old: Option<UserSession>,
+    user_type: &UserType,
+) -> Result<(UserSession, String)> {
+    if let Some(session) = old
+        && let Ok(Some(dashboard)) =
+            get_user_dashboard(&session.cookie_str, &session.user_type).await
+    {
+        return Ok((session, dashboard));
+    }
+    let (session, dashboard) = login(user_name, password, user_type).await?;
+    let dashboard = match dashboard {
+        Some(dashboard) => dashboard,
+        None => get_user_dashboard(&session.cookie_str, &session.user_type)
+            .await?
+            .ok_or(anyhow!("获取账号信息失败"))?,
+    };
+    Ok((session, dashboard))
+}
+
+async fn query_account(
+    user_name: &str,
+    password: &str,
+    old: Option<UserSession>,
+    user_type: &UserType,
+) -> Result<(UserSession, AccountSummary)> {
+    let (session, dashboard) = ensure_session(user_name, password, old, user_type).await?;
+    let online_list = get_online_list(&session.cookie_str, &session.user_type).await?;
+    let summary = AccountSummary::parse(user_name, &dashboard, &online_list)?;
+    Ok((session, summary))
+}
+
+// 同时查询多个保存的账号，user_names 为空时查询全部，不会改变当前登录的账号
+// via_vpn 为 true 时全部通过 WebVPN 登录，否则按各个账号设置的登录方式
+#[tauri::command(async)]
+pub async fn load_accounts_summary(
+    app: tauri::AppHandle,
+    user_names: Option<Vec<String>>,
+    via_vpn: bool,
+) -> Result<String, String> {
+    let app_state = app.state::<AppState>();
+    let (accounts, profile) = {
+        let setting = app_state.setting.read().await;
+        let accounts: Vec<SavedAccount> = setting
+            .account
+            .iter()
+            .filter(|a| {
+                user_names
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&a.user_name))
+            })
+            .cloned()
+            .collect();
+        (accounts, setting.current_webvpn_profile())
+    };
+    let sessions = app_state.sessions.read().await.clone();
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_LOGINS));
+    let mut tasks = JoinSet::new();
+    for (index, account) in accounts.into_iter().enumerate() {
+        let old = sessions.get(&account.user_name).cloned();
+        let user_type = user_type_for(&account, via_vpn, &profile);
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let res = query_account(&account.user_name, &account.password, old, &user_type).await;
+            (index, account.user_name, res)
+        });
+    }
+
+    let mut summaries = vec![];
+    while let Some(res) = tasks.join_next().await {
+        let (index, user_name, res) = res.map_err(|e| e.to_string())?;
+        let summary = match res {
+            Ok((session, summary)) => {
+                if let Some(balance) = summary.left_money {
+                    events::observe_balance(&app, &user_name, balance).await;
+                }
+                app_state.sessions.write().await.insert(user_name, session);
+                summary
+            }
+            Err(e) => AccountSummary::failed(user_name, e),
+        };
+        summaries.push((index, summary));
+    }
+    // 按配置里账号的顺序返回
+    summaries.sort_by_key(|(index, _)| *index);
+    let summaries: Vec<_> = summaries.into_iter().map(|(_, s)| s).collect();
+    Ok(serde_json::to_string(&summaries).unwrap_or_default())
+}
+
+// 切换到已经登录过的账号，不用登出再登录，返回新的 dashboard
+#[tauri::command(async)]
+pub async fn switch_session(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    user_name: String,
+) -> Result<String, String> {
+    let session = app_state
+        .sessions
+        .read()
+        .await
+        .get(&user_name)
+        .cloned()
+        .ok_or("该账号还没有登录过".to_string())?;
+    let dashboard = get_user_dashboard(&session.cookie_str, &session.user_type)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(dashboard) = dashboard else {
+        app_state.sessions.write().await.remove(&user_name);
+        events::emit(&app, AppEvent::SessionExpired { user_name });
+        return Err("该账号的登录已失效，请重新登录".to_string());
+    };
+    *app_state.cookie_str.write().await = Some(session.cookie_str);
+    *app_state.user_type.write().await = session.user_type;
+    *app_state.cur_account.write().await = user_name.clone();
+    events::emit(
+        &app,
+        AppEvent::LoginStateChanged {
+            user_name: user_name.clone(),
+            logged_in: true,
+        },
+    );
+    events::observe_dashboard(&app, &user_name, &dashboard).await;
+    Ok(dashboard)
+}
+
+#[tauri::command(async)]
+pub async fn set_account_preferences(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    user_name: String,
+    preferences: AccountPreferences,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .set_account_preferences(&user_name, preferences)
+        .map_err(|e| e.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+// 在校园网后台改了密码之后，先用新密码登录一次，成功了再保存
+#[tauri::command(async)]
+pub async fn update_account_password(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    user_name: String,
+    password: String,
+    via_vpn: bool,
+) -> Result<(), String> {
+    let user_type = {
+        let setting = app_state.setting.read().await;
+        let account = setting
+            .account
+            .iter()
+            .find(|a| a.user_name == user_name)
+            .ok_or(format!("没有保存账号 {user_name}"))?;
+        user_type_for(account, via_vpn, &setting.current_webvpn_profile())
+    };
+    let (session, _) = login(&user_name, &password, &user_type)
+        .await
+        .map_err(|e| format!("新密码验证失败：{e}"))?;
+    app_state
+        .sessions
+        .write()
+        .await
+        .insert(user_name.clone(), session);
+    app_state
+        .setting
+        .write()
+        .await
+        .set_account_password(&user_name, password)
+        .map_err(|e| e.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+// 删除保存的账号，当前登录的状态不受影响
+#[tauri::command(async)]
+pub async fn remove_account(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    user_name: String,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .remove_account(&user_name)
+        .map_err(|e| e.to_string())?;
+    app_state.sessions.write().await.remove(&user_name);
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+// 清除所有账号密码和登录状态，同步过的话远端的配置文件也用清除后的覆盖
+// 覆盖完再清除同步用的 WebDAV 密码和 OneDrive 登录状态
+#[tauri::command(async)]
+pub async fn forget_all_credentials(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+) -> Result<String, String> {
+    app_state.setting.write().await.clear_accounts();
+    app_state.sessions.write().await.clear();
+    *app_state.cookie_str.write().await = None;
+    *app_state.user_type.write().await = UserType::default();
+    VpnSession::clear(&app).map_err(|e| e.to_string())?;
+    // 不安排自动同步，没有同步基准的合并会把远端的账号又合并回来
+    app_state
+        .setting
+        .read()
+        .await
+        .write_setting(&app)
+        .map_err(|e| e.to_string())?;
+    let overwritten = crate::sync::overwrite_remote(&app).await;
+
+    let mut setting = app_state.setting.write().await;
+    setting.clear_passwords();
+    // 覆盖失败时远端还有账号，关掉自动同步，免得下次同步又合并回来
+    if overwritten.is_err() {
+        setting.set_auto_sync(false);
+    }
+    setting.write_setting(&app).map_err(|e| e.to_string())?;
+    drop(setting);
+    crate::onedrive::clear_token(&app).map_err(|e| e.to_string())?;
+
+    match overwritten {
+        Ok(true) => Ok("已清除所有账号和同步凭据，同步的配置文件也已覆盖".to_string()),
+        Ok(false) => Ok("已清除所有账号和同步凭据".to_string()),
+        Err(e) => Err(format!(
+            "已清除本机保存的所有账号和同步凭据，并关闭了自动同步，但没能覆盖同步的配置文件，里面还有账号密码，请手动删除：{e}"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_summary() {
+        let dashboard = include_str!("../../user-dashboard.json");
+        let summary = AccountSummary::parse("u1", dashboard, r#"[{"sessionId":"1"}]"#).unwrap();
+        assert_eq!(
+            summary,
+            AccountSummary {
+                user_name: "u1".to_string(),
+                left_money: Some(21.97),
+                use_flow: Some(48144.015),
+                left_flow: Some(74735.985),
+                online_count: Some(1),
+                error: None,
+            }
+        );
+        // 登录失效时返回的是登录页
+        assert!(AccountSummary::parse("u1", dashboard, "<html></html>").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Timelike};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{Manager, ipc::Channel};
+
+use crate::{
+    commands::load_user_online_log,
+    entities::{AppState, OnlineLogEvent},
+    setting::device_alias::{DeviceAlias, find_alias},
+    usage::{OnlineLog, OnlineLogRow, parse_online_log},
+    utils::{campus_offset, oui::normalize_mac, parse_date},
+};
+
+// 一台设备的使用情况，流量单位 MB，时长单位分钟
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceUsage {
+    pub mac: String,
+    pub name: Option<String>, // 本地记住的设备名
+    pub flow: f64,
+    pub ipv4_flow: f64,
+    pub ipv6_flow: f64,
+    pub minutes: f64,
+    pub sessions: usize,
+}
+
+// 一周里的某个小时，weekday 0 是周一
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeatmapCell {
+    pub weekday: u32,
+    pub hour: u32,
+    pub minutes: f64, // 不同设备同时在线会叠加
+    pub flow: f64,    // 一次在线的流量按时长平摊到每个小时
+}
+
+// 一天的使用情况，和校园网后台一样按下线时间算到哪一天
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub date: String, // YYYY-MM-DD
+    pub ipv4_down: f64,
+    pub ipv4_up: f64,
+    pub ipv6_down: f64,
+    pub ipv6_up: f64,
+    pub flow: f64,
+    pub minutes: f64,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageAnalytics {
+    pub summary: Value, // 校园网后台给的汇总，原样返回
+    pub daily: Vec<DailyUsage>,
+    pub heatmap: Vec<HeatmapCell>,
+    pub devices: Vec<DeviceUsage>,
+    pub sessions: usize,
+    pub average_session_minutes: f64,
+}
+
+// 按 MAC 地址汇总，流量多的在前
+pub fn device_breakdown(rows: &[OnlineLogRow], aliases: &[DeviceAlias]) -> Vec<DeviceUsage> {
+    let mut devices: Vec<DeviceUsage> = vec![];
+    for row in rows {
+        let mac = normalize_mac(&row.mac_address);
+        let i = match devices.iter().position(|d| d.mac == mac) {
+            Some(i) => i,
+            None => {
+                devices.push(DeviceUsage {
+                    name: find_alias(aliases, &mac).map(str::to_string),
+                    mac,
+                    ..Default::default()
+                });
+                devices.len() - 1
+            }
+        };
+        devices[i].flow += row.flow;
+        devices[i].ipv4_flow += row.ipv4_flow();
+        devices[i].ipv6_flow += row.ipv6_flow();
+        devices[i].minutes += row.time;
+        devices[i].sessions += 1;
+    }
+    devices.sort_by(|a, b| b.flow.total_cmp(&a.flow));
+    devices
+}
+
+// 7 * 24 个格子，先按星期再按小时排
+pub fn hour_of_week_heatmap(rows: &[OnlineLogRow], offset: &FixedOffset) -> Vec<HeatmapCell> {
+    let mut cells: Vec<HeatmapCell> = (0..7 * 24)
+        .map(|i| HeatmapCell {
+            weekday: i / 24,
+            hour: i % 24,
+            ..Default::default()
+        })
+        .collect();
+    for row in rows {
+        let parts = row.hourly_minutes(offset);
+        let total: f64 = parts.iter().map(|(_, minutes)| minutes).sum();
+        for (hour, minutes) in parts {
+            let cell =
+                &mut cells[(hour.weekday().num_days_from_monday() * 24 + hour.hour()) as usize];
+            cell.minutes += minutes;
+            if total > 0.0 {
+                cell.flow += row.flow * minutes / total;
+            }
+        }
+    }
+    cells
+}
+
+// start 到 end 每天一项，两头都包含
+pub fn daily_usage(
+    rows: &[OnlineLogRow],
+    start: NaiveDate,
+    end: NaiveDate,
+    offset: &FixedOffset,
+) -> Vec<DailyUsage> {
+    let mut days: Vec<DailyUsage> = start
+        .iter_days()
+        .take_while(|d| *d <= end)
+        .map(|d| DailyUsage {
+            date: d.format("%Y-%m-%d").to_string(),
+            ..Default::default()
+        })
+        .collect();
+    for row in rows {
+        let Some(logout) = DateTime::from_timestamp_millis(row.logout_time) else {
+            continue;
+        };
+        let date = logout.with_timezone(offset).date_naive();
+        if date < start || date > end {
+            continue;
+        }
+        let day = &mut days[(date - start).num_days() as usize];
+        day.ipv4_down += row.internet_down_flow;
+        day.ipv4_up += row.internet_up_flow;
+        day.ipv6_down += row.chinanet_down_flow;
+        day.ipv6_up += row.chinanet_up_flow;
+        day.flow += row.flow;
+        day.minutes += row.time;
+        day.cost += row.cost_money;
+    }
+    days
+}
+
+impl UsageAnalytics {
+    pub fn build(
+        log: &OnlineLog,
+        start: NaiveDate,
+        end: NaiveDate,
+        aliases: &[DeviceAlias],
+        offset: &FixedOffset,
+    ) -> Self {
+        let sessions = log.rows.len();
+        let average_session_minutes = match sessions {
+            0 => 0.0,
+            n => log.rows.iter().map(|r| r.time).sum::<f64>() / n as f64,
+        };
+        UsageAnalytics {
+            summary: log.summary.clone(),
+            daily: daily_usage(&log.rows, start, end, offset),
+            heatmap: hour_of_week_heatmap(&log.rows, offset),
+            devices: device_breakdown(&log.rows, aliases),
+            sessions,
+            average_session_minutes,
+        }
+    }
+}
+
+// 给定日期区间，返回按天、按一周里的小时、按设备汇总好的数据
+#[tauri::command(async)]
+pub async fn load_usage_analytics(
+    app: tauri::AppHandle,
+    start_date: String,
+    end_date: String,
+    on_event: Channel<OnlineLogEvent>,
+) -> Result<String, String> {
+    let start = parse_date(&start_date).map_err(|e| e.to_string())?;
+    let end = parse_date(&end_date).map_err(|e| e.to_string())?;
+    let json_str = load_user_online_log(app.clone(), start_date, end_date, on_event).await?;
+    let log = parse_online_log(&json_str).map_err(|e| e.to_string())?;
+    let app_state = app.state::<AppState>();
+    let aliases = app_state.setting.read().await.device_aliases.clone();
+    let analytics = UsageAnalytics::build(&log, start, end, &aliases, &campus_offset());
+    Ok(serde_json::to_string(&analytics).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1740787200000 是北京时间 2025-03-01（周六）08:00
+    const SAT_8AM: i64 = 1740787200000;
+    const HOUR: i64 = 3_600_000;
+
+    fn row(mac: &str, login_time: i64, minutes: i64, flow: f64) -> OnlineLogRow {
+        OnlineLogRow {
+            login_time,
+            logout_time: login_time + minutes * 60_000,
+            mac_address: mac.to_string(),
+            flow,
+            internet_down_flow: flow,
+            cost_money: 0.1,
+            time: minutes as f64,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_usage_analytics() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let log = OnlineLog {
+            rows: vec![
+                row("aa-bb-cc-00-00-01", SAT_8AM, 90, 300.0),
+                // 周日 23:30 到周一 00:30，下线算在周一
+                row(
+                    "11:22:33:44:55:66",
+                    SAT_8AM + 39 * HOUR + HOUR / 2,
+                    60,
+                    100.0,
+                ),
+                row("AABBCC000001", SAT_8AM + 24 * HOUR, 30, 50.0),
+            ],
+            ..Default::default()
+        };
+        let aliases = [DeviceAlias {
+            mac: "AABBCC000001".to_string(),
+            name: "手机".to_string(),
+        }];
+        let start = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2025, 3, 3).unwrap();
+        let res = UsageAnalytics::build(&log, start, end, &aliases, &offset);
+
+        assert_eq!(res.sessions, 3);
+        assert_eq!(res.average_session_minutes, 60.0);
+
+        let cell = |weekday: u32, hour: u32| &res.heatmap[(weekday * 24 + hour) as usize];
+        assert_eq!(res.heatmap.len(), 168);
+        assert_eq!((cell(5, 8).minutes, cell(5, 8).flow), (60.0, 200.0));
+        assert_eq!((cell(5, 9).minutes, cell(5, 9).flow), (30.0, 100.0));
+        assert_eq!((cell(6, 8).minutes, cell(6, 8).flow), (30.0, 50.0));
+        assert_eq!((cell(6, 23).minutes, cell(6, 23).flow), (30.0, 50.0));
+        assert_eq!((cell(0, 0).minutes, cell(0, 0).flow), (30.0, 50.0));
+
+        let daily: Vec<_> = res
+            .daily
+            .iter()
+            .map(|d| (d.date.as_str(), d.flow))
+            .collect();
+        assert_eq!(
+            daily,
+            [
+                ("2025-03-01", 300.0),
+                ("2025-03-02", 50.0),
+                ("2025-03-03", 100.0)
+            ]
+        );
+
+        assert_eq!(res.devices.len(), 2);
+        assert_eq!(res.devices[0].name.as_deref(), Some("手机"));
+        assert_eq!(res.devices[0].flow, 350.0);
+        assert_eq!(res.devices[0].sessions, 2);
+        assert_eq!(res.devices[1].mac, "112233445566");
+    }
+
+    #[test]
+    fn test_daily_usage_boundary() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        // 北京时间 2025-03-01 00:00，UTC 还是 2 月 28 日
+        let midnight = SAT_8AM - 8 * HOUR;
+        let rows = [
+            // 2 月 28 日 23:00 上线，23:59 下线，不在区间里
+            row("aa", midnight - HOUR, 59, 1.0),
+            // 跨过零点，按下线时间算在 3 月 1 日
+            row("bb", midnight - HOUR / 2, 60, 2.0),
+            // 3 月 31 日 23:59 下线，算在最后一天
+            row("cc", midnight + 31 * 24 * HOUR - 2 * HOUR, 119, 4.0),
+            // 4 月 1 日 00:00 下线，不在区间里
+            row("dd", midnight + 31 * 24 * HOUR - HOUR / 2, 30, 8.0),
+        ];
+        let start = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2025, 3, 31).unwrap();
+        let days = daily_usage(&rows, start, end, &offset);
+        assert_eq!(days.len(), 31);
+        assert_eq!((days[0].date.as_str(), days[0].flow), ("2025-03-01", 2.0));
+        assert_eq!((days[30].date.as_str(), days[30].flow), ("2025-03-31", 4.0));
+        assert_eq!(days.iter().map(|d| d.flow).sum::<f64>(), 6.0);
+    }
+}
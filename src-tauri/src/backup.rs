@@ -0,0 +1,431 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use zip::{AesMode, ZipArchive, ZipWriter, result::ZipError, write::SimpleFileOptions};
+
+use crate::{
+    entities::AppState,
+    setting::{Setting, account::SavedAccount},
+    sync::SyncBackendConfig,
+    utils::{get_store_path, write_private_file},
+};
+
+const MANIFEST_FILE: &str = "backup.json";
+const CONFIG_FILE: &str = "config.json";
+// 备份格式有变化时加一
+const BACKUP_VERSION: u32 = 1;
+// 登录凭据和会话，只有设了备份密码才导出，明文备份里不能有这些
+// config.json 里的账号密码和 WebDAV 密码在明文备份里会被清空
+const CREDENTIAL_FILES: [&str; 3] = [
+    "onedrive_token.json",
+    "sync_state.json",
+    "webvpn_session.json",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    app_version: String,
+    created_at: i64,
+    files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Backup {
+    manifest: Manifest,
+    encrypted: bool,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+// 恢复前给用户看的内容
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupPreview {
+    pub app_version: String,
+    pub created_at: i64,
+    pub encrypted: bool,
+    pub files: Vec<PreviewFile>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewFile {
+    pub name: String,
+    pub size: u64,
+    pub replace: bool, // 本地已有同名文件，会被覆盖
+}
+
+// 只备份数据目录下的普通文件，不允许带路径
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', ':'])
+        && name != MANIFEST_FILE
+}
+
+fn zip_error(e: ZipError) -> anyhow::Error {
+    match e {
+        ZipError::InvalidPassword => anyhow!("备份密码错误"),
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+            anyhow!("备份已加密，请输入密码")
+        }
+        e => anyhow!("备份文件已损坏：{e}"),
+    }
+}
+
+fn strip_passwords(config: &[u8]) -> Result<Vec<u8>> {
+    let mut setting: Setting = serde_json::from_slice(config)
+        .map_err(|e| anyhow!("配置文件已损坏，无法导出不加密的备份：{e}"))?;
+    setting.clear_passwords();
+    Ok(serde_json::to_vec(&setting)?)
+}
+
+pub fn create_backup(dir: &Path, app_version: &str, password: Option<&str>) -> Result<Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if password.is_none() && CREDENTIAL_FILES.contains(&name.as_str()) {
+            continue;
+        }
+        if is_valid_name(&name) && entry.file_type()?.is_file() {
+            let mut data = fs::read(entry.path())?;
+            if password.is_none() && name == CONFIG_FILE {
+                data = strip_passwords(&data)?;
+            }
+            files.insert(name, data);
+        }
+    }
+
+    let mut options = SimpleFileOptions::default();
+    if let Some(password) = password {
+        options = options.with_aes_encryption(AesMode::Aes256, password);
+    }
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(&serde_json::to_vec(&Manifest {
+        version: BACKUP_VERSION,
+        app_version: app_version.to_string(),
+        created_at: Local::now().timestamp(),
+        files: files.keys().cloned().collect(),
+    })?)?;
+    for (name, data) in &files {
+        zip.start_file(name, options)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+// 读出整个备份并检查，有任何问题都不恢复
+pub fn read_backup(data: &[u8], password: Option<&str>) -> Result<Backup> {
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(|_| anyhow!("不是有效的备份文件"))?;
+    let Some(manifest_index) = zip.index_for_name(MANIFEST_FILE) else {
+        return Err(anyhow!("不是本软件导出的备份文件"));
+    };
+    let encrypted = zip.by_index_raw(manifest_index)?.encrypted();
+    let mut read = |name: &str| -> Result<Vec<u8>> {
+        let mut file = match password {
+            Some(password) => zip.by_name_decrypt(name, password.as_bytes()),
+            None => zip.by_name(name),
+        }
+        .map_err(zip_error)?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)
+            .map_err(|e| anyhow!("备份文件已损坏：{e}"))?;
+        Ok(buf)
+    };
+
+    let manifest: Manifest =
+        serde_json::from_slice(&read(MANIFEST_FILE)?).map_err(|_| anyhow!("不是有效的备份文件"))?;
+    if manifest.version > BACKUP_VERSION {
+        return Err(anyhow!("备份是新版本软件导出的，请先更新软件"));
+    }
+
+    let mut files = BTreeMap::new();
+    for name in &manifest.files {
+        if !is_valid_name(name) {
+            return Err(anyhow!("备份中有不合法的文件名：{name}"));
+        }
+        files.insert(name.clone(), read(name)?);
+    }
+    if let Some(config) = files.get(CONFIG_FILE) {
+        serde_json::from_slice::<Setting>(config)
+            .map_err(|e| anyhow!("备份中的配置文件已损坏：{e}"))?;
+    }
+    Ok(Backup {
+        manifest,
+        encrypted,
+        files,
+    })
+}
+
+impl Backup {
+    pub fn preview(&self, dir: &Path) -> BackupPreview {
+        BackupPreview {
+            app_version: self.manifest.app_version.clone(),
+            created_at: self.manifest.created_at,
+            encrypted: self.encrypted,
+            files: self
+                .files
+                .iter()
+                .map(|(name, data)| PreviewFile {
+                    name: name.clone(),
+                    size: data.len() as u64,
+                    replace: dir.join(name).exists(),
+                })
+                .collect(),
+        }
+    }
+
+    // 备份里没有的文件保留，有的覆盖，里面可能有密码，都按凭据文件的权限写
+    pub fn restore(&self, dir: &Path) -> Result<()> {
+        for (name, data) in &self.files {
+            let tmp = dir.join(format!(".{name}.tmp"));
+            write_private_file(&tmp, data)?;
+            fs::rename(&tmp, dir.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+// 背景图片存的是绝对路径，换了电脑或者用户名就对不上了
+fn fix_background_image_path(setting: &mut Setting, dir: &Path) {
+    let Some(path) = &setting.background_image_path else {
+        return;
+    };
+    let restored = Path::new(path)
+        .file_name()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists());
+    setting.background_image_path = restored.and_then(|p| p.to_str().map(str::to_string));
+}
+
+// 明文备份里没有密码，恢复后沿用本机保存的密码，免得恢复完还要重新输入
+fn keep_local_passwords(setting: &mut Setting, local: &Setting) {
+    for account in setting.account.iter_mut().filter(|a| a.password.is_empty()) {
+        if let Some(old) = local
+            .account
+            .iter()
+            .find(|a| a.user_name == account.user_name)
+        {
+            account.password = old.password.clone();
+        }
+    }
+    if let (
+        Some(SyncBackendConfig::WebDav {
+            url,
+            username,
+            password,
+        }),
+        Some(SyncBackendConfig::WebDav {
+            url: old_url,
+            username: old_username,
+            password: old_password,
+        }),
+    ) = (&mut setting.sync_backend, &local.sync_backend)
+        && password.is_empty()
+        && url == old_url
+        && username == old_username
+    {
+        *password = old_password.clone();
+    }
+}
+
+fn read_backup_file(path: &str, password: Option<String>) -> Result<Backup> {
+    let data = fs::read(path).map_err(|e| anyhow!("无法读取备份文件：{e}"))?;
+    read_backup(&data, password.as_deref().filter(|p| !p.is_empty()))
+}
+
+#[tauri::command(async)]
+pub async fn export_backup(
+    app: tauri::AppHandle,
+    path: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    let dir = get_store_path(&app).map_err(|e| e.to_string())?;
+    let data = create_backup(
+        &dir,
+        &app.package_info().version.to_string(),
+        password.as_deref().filter(|p| !p.is_empty()),
+    )
+    .map_err(|e| e.to_string())?;
+    fs::write(path, data).map_err(|e| e.to_string())
+}
+
+#[tauri::command(async)]
+pub async fn preview_backup(
+    app: tauri::AppHandle,
+    path: String,
+    password: Option<String>,
+) -> Result<String, String> {
+    let backup = read_backup_file(&path, password).map_err(|e| e.to_string())?;
+    let dir = get_store_path(&app).map_err(|e| e.to_string())?;
+    Ok(serde_json::to_string(&backup.preview(&dir)).unwrap_or_default())
+}
+
+#[tauri::command(async)]
+pub async fn restore_backup(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    path: String,
+    password: Option<String>,
+) -> Result<(), String> {
+    let backup = read_backup_file(&path, password).map_err(|e| e.to_string())?;
+    let dir = get_store_path(&app).map_err(|e| e.to_string())?;
+    let local = app_state.setting.read().await.clone();
+    backup.restore(&dir).map_err(|e| e.to_string())?;
+
+    let mut setting = Setting::load_setting(&app).map_err(|e| e.to_string())?;
+    fix_background_image_path(&mut setting, &dir);
+    if !backup.encrypted {
+        keep_local_passwords(&mut setting, &local);
+    }
+    setting
+        .write_synced_setting(&app)
+        .map_err(|e| e.to_string())?;
+    *app_state.setting.write().await = setting;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ustb-backup-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_backup_restore() {
+        let src = temp_dir("src");
+        let config = serde_json::to_vec(&Setting::default()).unwrap();
+        fs::write(src.join(CONFIG_FILE), &config).unwrap();
+        fs::write(src.join("123.json"), b"[]").unwrap();
+        fs::write(src.join(".hidden"), b"").unwrap();
+
+        let data = create_backup(&src, "1.0.0", Some("secret")).unwrap();
+        assert_eq!(
+            read_backup(&data, None).unwrap_err().to_string(),
+            "备份已加密，请输入密码"
+        );
+        assert_eq!(
+            read_backup(&data, Some("wrong")).unwrap_err().to_string(),
+            "备份密码错误"
+        );
+        let backup = read_backup(&data, Some("secret")).unwrap();
+        assert!(backup.encrypted);
+
+        let dest = temp_dir("dest");
+        fs::write(dest.join("123.json"), b"[1]").unwrap();
+        let preview = backup.preview(&dest);
+        assert_eq!(
+            preview.files,
+            vec![
+                PreviewFile {
+                    name: "123.json".to_string(),
+                    size: 2,
+                    replace: true,
+                },
+                PreviewFile {
+                    name: CONFIG_FILE.to_string(),
+                    size: config.len() as u64,
+                    replace: false,
+                },
+            ]
+        );
+        backup.restore(&dest).unwrap();
+        assert_eq!(fs::read(dest.join("123.json")).unwrap(), b"[]");
+        assert!(!dest.join(".hidden").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dest.join(CONFIG_FILE))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // 凭据只在加密的备份里
+        fs::write(src.join("onedrive_token.json"), b"{}").unwrap();
+        let encrypted = create_backup(&src, "1.0.0", Some("secret")).unwrap();
+        let encrypted = read_backup(&encrypted, Some("secret")).unwrap();
+        assert!(encrypted.files.contains_key("onedrive_token.json"));
+        let plain = read_backup(&create_backup(&src, "1.0.0", None).unwrap(), None).unwrap();
+        assert!(!plain.encrypted);
+        assert!(!plain.files.contains_key("onedrive_token.json"));
+        assert!(read_backup(b"not a zip", None).is_err());
+
+        fs::remove_dir_all(&src).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn test_plain_backup_has_no_passwords() {
+        let src = temp_dir("plain");
+        let webdav = |password: &str| SyncBackendConfig::WebDav {
+            url: "https://dav.example.com/ustb".to_string(),
+            username: "me".to_string(),
+            password: password.to_string(),
+        };
+        let setting = Setting {
+            account: vec![SavedAccount::new("u1".to_string(), "p1".to_string())],
+            sync_backend: Some(webdav("dav-secret")),
+            ..Default::default()
+        };
+        fs::write(src.join(CONFIG_FILE), serde_json::to_vec(&setting).unwrap()).unwrap();
+
+        let plain = read_backup(&create_backup(&src, "1.0.0", None).unwrap(), None).unwrap();
+        let config = String::from_utf8(plain.files[CONFIG_FILE].clone()).unwrap();
+        assert!(!config.contains("p1"));
+        assert!(!config.contains("dav-secret"));
+        let mut restored: Setting = serde_json::from_str(&config).unwrap();
+        assert_eq!(restored.account[0].user_name, "u1");
+        assert_eq!(restored.account[0].password, "");
+        assert_eq!(restored.sync_backend, Some(webdav("")));
+
+        // 恢复时用回本机的密码
+        keep_local_passwords(&mut restored, &setting);
+        assert_eq!(restored.account[0].password, "p1");
+        assert_eq!(restored.sync_backend, Some(webdav("dav-secret")));
+
+        // 加密备份原样保留
+        let encrypted = create_backup(&src, "1.0.0", Some("secret")).unwrap();
+        let encrypted = read_backup(&encrypted, Some("secret")).unwrap();
+        let config = String::from_utf8(encrypted.files[CONFIG_FILE].clone()).unwrap();
+        assert!(config.contains("p1") && config.contains("dav-secret"));
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn test_fix_background_image_path() {
+        let dir = temp_dir("bg");
+        fs::write(dir.join("bg.png"), b"").unwrap();
+        let mut setting = Setting {
+            background_image_path: Some("/old/ustb-wifi-tools/bg.png".to_string()),
+            ..Default::default()
+        };
+        fix_background_image_path(&mut setting, &dir);
+        assert_eq!(
+            setting.background_image_path.as_deref(),
+            dir.join("bg.png").to_str()
+        );
+        setting.background_image_path = Some("/old/missing.png".to_string());
+        fix_background_image_path(&mut setting, &dir);
+        assert_eq!(setting.background_image_path, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,30 @@
-use std::{collections::HashSet, net::IpAddr, time::Duration};
+use std::{net::IpAddr, time::Duration};
 
-use chrono::DateTime;
-use reqwest::Client;
-use serde::Serialize;
-use tauri::{Manager, ipc::Channel, utils::config::WindowConfig};
+use tauri::{
+    Manager, Url,
+    ipc::Channel,
+    utils::config::WindowConfig,
+    webview::{Cookie, PageLoadEvent},
+};
 
 use crate::{
+    devices::{BoundDevice, current_device_macs},
     electric_bill::update_ammeter,
-    entities::{AppState, DownloadEvent, UserType},
+    entities::{AppState, DownloadEvent, OnlineLogEvent, UserSession, UserType},
+    events::{self, AppEvent},
+    kick::OnlineSession,
+    proxy,
     requests::*,
+    session::VpnSession,
     setting::Setting,
-    utils::{get_cookie_str, get_store_path, webvpn},
+    usage::fetch_online_log,
+    utils::{
+        complete_month_pay_data, get_cookie_str, get_store_path,
+        interfaces::{LoginBinding, detect_campus_interface, list_interfaces, pick_login_binding},
+        ip_location::{self, LocationSource},
+        parse_date,
+        webvpn::WebVpnProfile,
+    },
 };
 
 #[cfg(not(any(target_os = "android", target_os = "linux")))]
@@ -21,7 +35,7 @@ use crate::utils::update;
 //     account: String,
 //     app_state: tauri::State<'_, AppState>,
 // ) -> Result<String, String> {
-//     let user_type = *app_state.user_type.read().await;
+//     let user_type = app_state.user_type.read().await.clone();
 //     match user_type {
 //         UserType::Normal | UserType::ViaVpn => {
 //             let cookie = get_cookie_str(&app_state).await?;
@@ -43,52 +57,258 @@ pub async fn get_cookie(
 ) -> Result<Option<String>, String> {
     let app_state = app.state::<AppState>();
 
-    let (cookie_str, user_dashboard) = if !via_vpn {
-        simulate_login(&user_name, &password)
-            .await
-            .map_err(|err| err.to_string())?
+    let user_type = if via_vpn {
+        UserType::ViaVpn(app_state.setting.read().await.current_webvpn_profile())
     } else {
-        simulate_login_via_vpn(&user_name, &password)
+        UserType::Normal
+    };
+    let (cookie_str, user_dashboard) = match &user_type {
+        UserType::Normal => simulate_login(&user_name, &password)
+            .await
+            .map_err(|err| err.to_string())?,
+        UserType::ViaVpn(profile) => simulate_login_via_vpn(profile, &user_name, &password)
             .await
-            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())?,
     };
     match cookie_str {
         Some(cookie_str) => {
-            dbg!(&cookie_str);
-            *app_state.cookie_str.write().await = Some(cookie_str.clone());
-            *app_state.user_type.write().await = if via_vpn {
-                UserType::ViaVpn
-            } else {
-                UserType::Normal
-            };
-            app_state
-                .setting
-                .write()
-                .await
-                .set_account(user_name, password);
-            app_state
-                .setting
-                .read()
-                .await
-                .write_setting(&app)
-                .map_err(|e| e.to_string())?;
-            cookie_str
+            save_login(
+                &app,
+                user_type,
+                user_name,
+                password,
+                cookie_str,
+                user_dashboard.as_deref(),
+            )
+            .await?
         }
         None => return Err("用户名或密码错误！".into()),
     };
     Ok(user_dashboard)
 }
 
+// 登录成功之后记录状态和账号，WebVPN 登录还要保存票据以便下次启动时恢复
+async fn save_login(
+    app: &tauri::AppHandle,
+    user_type: UserType,
+    user_name: String,
+    password: String,
+    cookie_str: String,
+    dashboard: Option<&str>,
+) -> Result<(), String> {
+    let app_state = app.state::<AppState>();
+    if let UserType::ViaVpn(profile) = &user_type {
+        VpnSession::new(profile.clone(), user_name.clone(), cookie_str.clone())
+            .save(app)
+            .map_err(|e| e.to_string())?;
+    }
+    app_state.sessions.write().await.insert(
+        user_name.clone(),
+        UserSession {
+            cookie_str: cookie_str.clone(),
+            user_type: user_type.clone(),
+        },
+    );
+    *app_state.cookie_str.write().await = Some(cookie_str);
+    *app_state.user_type.write().await = user_type;
+    events::emit(
+        app,
+        AppEvent::LoginStateChanged {
+            user_name: user_name.clone(),
+            logged_in: true,
+        },
+    );
+    if let Some(dashboard) = dashboard {
+        events::observe_dashboard(app, &user_name, dashboard).await;
+    }
+    app_state
+        .setting
+        .write()
+        .await
+        .set_account(user_name, password);
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(app)
+        .map_err(|e| e.to_string())
+}
+
+// 在窗口里登录 WebVPN 网关，可以使用统一身份认证（CAS）或者短信验证码，
+// 拿到网关的票据之后再用校园网密码登录校园网后台
+#[tauri::command(async)]
+pub async fn login_via_webvpn_portal(
+    app: tauri::AppHandle,
+    user_name: String,
+    password: String,
+    cas: bool,
+) -> Result<Option<String>, String> {
+    if app.get_webview_window("webvpn_login").is_some() {
+        return Err("已经打开一个登录窗口了".to_string());
+    }
+    let profile = app
+        .state::<AppState>()
+        .setting
+        .read()
+        .await
+        .current_webvpn_profile();
+    let login_url = if cas {
+        profile.gateway_url("/login?cas_login=true")
+    } else {
+        profile.gateway_url("/login")
+    };
+    let login_url = Url::parse(&login_url).map_err(|e| e.to_string())?;
+    let gateway_url = Url::parse(&profile.gateway_url("/")).map_err(|e| e.to_string())?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<String>();
+    let tx = std::sync::Mutex::new(Some(tx));
+    let domain = profile.domain();
+    let mut win = tauri::WebviewWindowBuilder::new(
+        &app,
+        "webvpn_login",
+        tauri::WebviewUrl::External(login_url),
+    )
+    .on_page_load(move |window, payload| {
+        let url = payload.url();
+        // 登录成功后会跳转到网关首页
+        let logged_in = matches!(payload.event(), PageLoadEvent::Finished)
+            && url.host_str() == Some(domain.as_str())
+            && !["/login", "/do-login", "/cas", "/sms"]
+                .iter()
+                .any(|p| url.path().starts_with(p));
+        if !logged_in {
+            return;
+        }
+        let Some(tx) = tx.lock().ok().and_then(|mut tx| tx.take()) else {
+            return;
+        };
+        let gateway_url = gateway_url.clone();
+        // 在 Windows 上同步读取 cookie 会卡死，放到别的线程去
+        std::thread::spawn(move || {
+            let cookie = window
+                .cookies_for_url(gateway_url)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|c| c.name().starts_with("wengine_vpn_ticket"));
+            if let Some(cookie) = cookie {
+                let _ = tx.send(format!("{}={}", cookie.name(), cookie.value()));
+            }
+            let _ = window.close();
+        });
+    });
+
+    #[cfg(not(target_os = "android"))]
+    {
+        win = win.inner_size(480.0, 670.0).title("WebVPN 登录");
+    }
+    win.build()
+        .map_err(|e| format!("Error when building the webvpn login window: {}", e))?;
+
+    let cookie_str = tokio::time::timeout(Duration::from_secs(300), rx)
+        .await
+        .map_err(|_| "登录超时".to_string())?
+        .map_err(|_| "登录窗口已关闭，未获取到 WebVPN 票据".to_string())?;
+
+    let (cookie_str, user_dashboard) =
+        login_zifuwu_via_vpn(&profile, &cookie_str, &user_name, &password)
+            .await
+            .map_err(|e| e.to_string())?;
+    match cookie_str {
+        Some(cookie_str) => {
+            save_login(
+                &app,
+                UserType::ViaVpn(profile),
+                user_name,
+                password,
+                cookie_str,
+                user_dashboard.as_deref(),
+            )
+            .await?
+        }
+        None => return Err("校园网后台用户名或密码错误！".into()),
+    };
+    Ok(user_dashboard)
+}
+
+// 启动时尝试恢复上次的 WebVPN 登录，返回 [用户名, dashboard]，没有可恢复的返回 None
+#[tauri::command(async)]
+pub async fn restore_webvpn_session(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    let app_state = app.state::<AppState>();
+    if app_state.cookie_str.read().await.is_some() {
+        return Ok(None);
+    }
+    let Some(session) = VpnSession::load(&app).map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    let user_type = UserType::ViaVpn(session.profile.clone());
+    // 网络不通时保留票据，下次启动还能再试
+    let user_dashboard = if session.is_expired() {
+        None
+    } else {
+        get_user_dashboard(&session.cookie_str, &user_type)
+            .await
+            .map_err(|e| format!("恢复 WebVPN 登录失败，请检查网络: {}", e))?
+    };
+    let Some(user_dashboard) = user_dashboard else {
+        // 票据已经失效
+        VpnSession::clear(&app).map_err(|e| e.to_string())?;
+        events::emit(
+            &app,
+            AppEvent::SessionExpired {
+                user_name: session.user_name,
+            },
+        );
+        return Ok(None);
+    };
+
+    app_state.sessions.write().await.insert(
+        session.user_name.clone(),
+        UserSession {
+            cookie_str: session.cookie_str.clone(),
+            user_type: user_type.clone(),
+        },
+    );
+    *app_state.cookie_str.write().await = Some(session.cookie_str);
+    *app_state.user_type.write().await = user_type;
+    *app_state.cur_account.write().await = session.user_name.clone();
+    events::emit(
+        &app,
+        AppEvent::LoginStateChanged {
+            user_name: session.user_name.clone(),
+            logged_in: true,
+        },
+    );
+    events::observe_dashboard(&app, &session.user_name, &user_dashboard).await;
+    Ok(Some(
+        serde_json::json!([session.user_name, user_dashboard]).to_string(),
+    ))
+}
+
 #[tauri::command(async)]
 pub async fn logout(
+    app: tauri::AppHandle,
     app_state: tauri::State<'_, AppState>,
     window: tauri::Webview,
 ) -> Result<String, String> {
     if app_state.cookie_str.read().await.is_none() {
         return Err("没登录之前不许登出😠".into());
     }
-    *app_state.cookie_str.write().await = None;
+    VpnSession::clear(&app).map_err(|e| e.to_string())?;
+    // 其他账号的会话留着，多账号概览还能用
+    let cookie_str = app_state.cookie_str.write().await.take();
+    app_state
+        .sessions
+        .write()
+        .await
+        .retain(|_, session| Some(&session.cookie_str) != cookie_str.as_ref());
     *app_state.user_type.write().await = UserType::default(); // 这之前有个bug一直没人发现，说明没人用我的 app 😭
+    events::emit(
+        &app,
+        AppEvent::LoginStateChanged {
+            user_name: app_state.cur_account.read().await.clone(),
+            logged_in: false,
+        },
+    );
     window
         .eval("window.location.reload();")
         .map_err(|err| format!("刷新网页错误：{}", err))?;
@@ -97,33 +317,52 @@ pub async fn logout(
 
 #[tauri::command(async)]
 pub async fn refresh_user_dashboard(
+    app: tauri::AppHandle,
     app_state: tauri::State<'_, AppState>,
 ) -> Result<String, String> {
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-    match get_user_dashboard(&cookie_str, user_type).await {
-        Ok(Some(str)) => Ok(str),
-        Ok(None) => Err("请确认是否已经登录".to_string()),
+    let user_type = app_state.user_type.read().await.clone();
+    let user_name = app_state.cur_account.read().await.clone();
+    match get_user_dashboard(&cookie_str, &user_type).await {
+        Ok(Some(str)) => {
+            events::observe_dashboard(&app, &user_name, &str).await;
+            Ok(str)
+        }
+        Ok(None) => {
+            events::emit(&app, AppEvent::SessionExpired { user_name });
+            Err("请确认是否已经登录".to_string())
+        }
         Err(e) => Err(format!("Request Error，检查是否在校园网内: {}", e)),
     }
 }
 
 #[tauri::command(async)]
-pub async fn load_online_list(app_state: tauri::State<'_, AppState>) -> Result<String, String> {
+pub async fn load_online_list(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+) -> Result<String, String> {
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-    get_online_list(&cookie_str, user_type)
+    let user_type = app_state.user_type.read().await.clone();
+    let json_str = get_online_list(&cookie_str, &user_type)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    if let Ok(sessions) = serde_json::from_str::<Vec<OnlineSession>>(&json_str) {
+        let user_name = app_state.cur_account.read().await.clone();
+        events::observe_online(&app, &user_name, &sessions).await;
+    }
+    // 解析失败就原样返回，不影响显示
+    Ok(ip_location::annotate_online_list(&json_str).unwrap_or(json_str))
 }
 
 #[tauri::command(async)]
 pub async fn load_login_history(app_state: tauri::State<'_, AppState>) -> Result<String, String> {
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-    get_login_history(&cookie_str, user_type)
+    let user_type = app_state.user_type.read().await.clone();
+    let json_str = get_login_history(&cookie_str, &user_type)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    // 解析失败就原样返回，不影响显示
+    Ok(ip_location::annotate_login_history(&json_str).unwrap_or(json_str))
 }
 
 #[tauri::command(async)]
@@ -132,8 +371,8 @@ pub async fn do_to_offline(
     session_id: String,
 ) -> Result<(), String> {
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-    to_offline(&cookie_str, user_type, &session_id)
+    let user_type = app_state.user_type.read().await.clone();
+    to_offline(&cookie_str, &user_type, &session_id)
         .await
         .map_err(|e| e.to_string())
 }
@@ -142,7 +381,7 @@ pub async fn do_to_offline(
 pub async fn load_month_pay(app: tauri::AppHandle, year: u16) -> Result<String, String> {
     let app_state = app.state::<AppState>();
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
+    let user_type = app_state.user_type.read().await.clone();
     // if let UserType::LocalUser = user_type {
     //     let month_pay_info = app_state
     //         .cur_account
@@ -153,7 +392,10 @@ pub async fn load_month_pay(app: tauri::AppHandle, year: u16) -> Result<String,
     //     return Ok(serde_json::json!(month_pay_info).to_string());
     // }
 
-    get_month_pay(&cookie_str, year, user_type)
+    let json_str = get_month_pay(&cookie_str, year, &user_type)
+        .await
+        .map_err(|e| e.to_string())?;
+    complete_month_pay_data(&json_str, year, &cookie_str, &user_type)
         .await
         .map_err(|e| e.to_string())
 }
@@ -161,27 +403,29 @@ pub async fn load_month_pay(app: tauri::AppHandle, year: u16) -> Result<String,
 #[tauri::command(async)]
 pub async fn load_user_online_log(
     app: tauri::AppHandle,
-    start_date: i64,
-    end_date: i64,
+    start_date: String,
+    end_date: String,
+    on_event: Channel<OnlineLogEvent>,
 ) -> Result<String, String> {
     let app_state = app.state::<AppState>();
+    let start_date = parse_date(&start_date).map_err(|e| e.to_string())?;
+    let end_date = parse_date(&end_date).map_err(|e| e.to_string())?;
     if start_date > end_date {
         return Err("起始日期比结束日期更大。。。".to_string());
     }
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
+    let user_type = app_state.user_type.read().await.clone();
 
     match user_type {
-        UserType::Normal | UserType::ViaVpn => {
-            let start_date = DateTime::from_timestamp(start_date, 0)
-                .unwrap()
-                .format("%Y-%m-%d")
-                .to_string();
-            let end_date = DateTime::from_timestamp(end_date, 0)
-                .unwrap()
-                .format("%Y-%m-%d")
-                .to_string();
-            get_user_online_log(&cookie_str, &start_date, &end_date, user_type).await
+        UserType::Normal | UserType::ViaVpn(_) => {
+            fetch_online_log(
+                &cookie_str,
+                start_date,
+                end_date,
+                &user_type,
+                Some(&on_event),
+            )
+            .await
         }
     }
     .map_err(|e| {
@@ -193,81 +437,47 @@ pub async fn load_user_online_log(
     })
 }
 
-#[tauri::command(async)]
-pub async fn load_mac_address(app_state: tauri::State<'_, AppState>) -> Result<String, String> {
-    let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-
-    get_mac_address(&cookie_str, user_type)
-        .await
-        .map(|res| serde_json::to_string(&res).unwrap_or_default())
-        .map_err(|e| e.to_string())
+#[tauri::command]
+pub fn get_current_device_mac() -> Result<String, String> {
+    let macs = current_device_macs().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!(macs).to_string())
 }
 
-#[tauri::command]
-pub async fn set_mac_custom_name(
+// 本机的网卡，以及自动检测到的校园网网卡
+#[tauri::command(async)]
+pub async fn list_network_interfaces(
     app_state: tauri::State<'_, AppState>,
-    mac_address: String,
-    terminal_name: String,
-    ajax_csrf_token: String,
 ) -> Result<String, String> {
-    let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-    update_terminal_name(
-        &cookie_str,
-        user_type,
-        &mac_address,
-        &terminal_name,
-        &ajax_csrf_token,
-    )
-    .await
-    .map_err(|e| e.to_string())
-}
-
-#[derive(Debug, Serialize)]
-struct MacAddress {
-    iface_name: String,
-    mac_address: String,
-}
-
-#[tauri::command]
-pub fn get_current_device_mac() -> Result<String, String> {
-    use if_addrs::Interface;
-    let ifaces = if_addrs::get_if_addrs()
-        .map_err(|e| e.to_string())?
-        .into_iter()
-        .filter_map(|iface| match iface.addr {
-            if_addrs::IfAddr::V4(_) if !iface.is_loopback() => Some(iface),
-            _ => None,
-        })
-        .collect::<Vec<Interface>>();
-
-    let macs: Vec<MacAddress> = ifaces
-        .into_iter()
-        .map(|iface| MacAddress {
-            iface_name: iface.name.clone(),
-            mac_address: mac_address::mac_address_by_name(&iface.name)
-                .unwrap_or_default()
-                .unwrap_or_default()
-                .to_string()
-                .replace(':', ""),
-        })
-        .collect();
-    Ok(serde_json::json!(macs).to_string())
+    let interfaces = list_interfaces().map_err(|e| e.to_string())?;
+    let detected = detect_campus_interface(&interfaces).map(|i| i.name.clone());
+    let selected = app_state.setting.read().await.login_interface.clone();
+    Ok(serde_json::json!({
+        "interfaces": interfaces,
+        "detected": detected,
+        "selected": selected,
+    })
+    .to_string())
 }
 
-// 传进来的应该是不需要解绑的，提醒。
 #[tauri::command(async)]
-pub async fn do_unbind_mac(
+pub async fn set_login_interface(
+    app: tauri::AppHandle,
     app_state: tauri::State<'_, AppState>,
-    mac: String,
-    ajax_csrf_token: String,
+    login_interface: Option<String>,
 ) -> Result<(), String> {
-    let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
-
-    unbind_mac(&cookie_str, user_type, &mac, &ajax_csrf_token)
+    // 保存前检查一下，输错的 IP 地址不要等到登录时才报错
+    let interfaces = list_interfaces().map_err(|e| e.to_string())?;
+    pick_login_binding(&interfaces, login_interface.as_deref()).map_err(|e| e.to_string())?;
+    app_state
+        .setting
+        .write()
+        .await
+        .set_login_interface(login_interface);
+    app_state
+        .setting
+        .read()
         .await
+        .write_setting(&app)
         .map_err(|e| e.to_string())
 }
 
@@ -303,6 +513,111 @@ pub fn open_speed_test(app_handle: tauri::AppHandle, site_num: i32) -> Result<()
     .map(|_| ())
 }
 
+#[tauri::command(async)]
+pub async fn open_webvpn_browser(app_handle: tauri::AppHandle, url: String) -> Result<(), String> {
+    let app_state = app_handle.state::<AppState>();
+    let UserType::ViaVpn(profile) = app_state.user_type.read().await.clone() else {
+        return Err("请先以“我不在校园网”模式登录".to_string());
+    };
+    let cookie_str = get_cookie_str(&app_state).await?;
+    if app_handle.get_webview_window("webvpn").is_some() {
+        return Err("已经打开一个 WebVPN 窗口了".to_string());
+    }
+
+    let url = Url::parse(url.trim()).map_err(|e| format!("网址格式错误：{}", e))?;
+    let url = if profile.is_campus_url(&url) {
+        profile
+            .translate_up(url.as_str())
+            .and_then(|vpn_url| Ok(Url::parse(&vpn_url)?))
+            .map_err(|e| e.to_string())?
+    } else {
+        url
+    };
+
+    let app = app_handle.clone();
+    let nav_profile = profile.clone();
+    let mut win = tauri::WebviewWindowBuilder::new(
+        &app_handle,
+        "webvpn",
+        tauri::WebviewUrl::External("about:blank".parse().unwrap()),
+    )
+    // 页面里跳转到校内地址时，改成对应的 WebVPN 地址再打开
+    .on_navigation(move |url| {
+        if !nav_profile.is_campus_url(url) {
+            return true;
+        }
+        let Ok(vpn_url) = nav_profile.translate_up(url.as_str()) else {
+            return true;
+        };
+        let Ok(vpn_url) = Url::parse(&vpn_url) else {
+            return true;
+        };
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Some(window) = app.get_webview_window("webvpn") {
+                let _ = window.navigate(vpn_url);
+            }
+        });
+        false
+    });
+
+    #[cfg(not(target_os = "android"))]
+    {
+        win = win.inner_size(1000.0, 700.0).title("WebVPN");
+    }
+
+    let window = win
+        .build()
+        .map_err(|e| format!("Error when building the webvpn window: {}", e))?;
+
+    // 把登录 elib 得到的 cookie 塞进去，这样不用再登录一次
+    let (name, value) = cookie_str
+        .split_once('=')
+        .ok_or("Cookie 格式不正确".to_string())?;
+    window
+        .set_cookie(
+            Cookie::build((name.to_string(), value.to_string()))
+                .domain(profile.domain())
+                .path("/")
+                .secure(profile.is_https())
+                .build(),
+        )
+        .map_err(|e| format!("设置 Cookie 失败：{}", e))?;
+    window.navigate(url).map_err(|e| e.to_string())
+}
+
+#[tauri::command(async)]
+pub async fn start_webvpn_proxy(
+    app: tauri::AppHandle,
+    port: Option<u16>,
+) -> Result<String, String> {
+    let app_state = app.state::<AppState>();
+    if !matches!(*app_state.user_type.read().await, UserType::ViaVpn(_)) {
+        return Err("请先以“我不在校园网”模式登录".to_string());
+    }
+    if app_state.webvpn_proxy.read().await.is_some() {
+        return Err("代理已经在运行了".to_string());
+    }
+    let listener = proxy::bind(port.unwrap_or(proxy::DEFAULT_PROXY_PORT))
+        .await
+        .map_err(|e| format!("端口被占用或无法监听：{}", e))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let handle = tauri::async_runtime::spawn(proxy::serve(listener, app.clone()));
+    *app_state.webvpn_proxy.write().await = Some(handle);
+    Ok(format!("http://{}", addr))
+}
+
+#[tauri::command(async)]
+pub async fn stop_webvpn_proxy(app_state: tauri::State<'_, AppState>) -> Result<(), String> {
+    match app_state.webvpn_proxy.write().await.take() {
+        Some(handle) => {
+            handle.abort();
+            Ok(())
+        }
+        None => Err("代理没有在运行".to_string()),
+    }
+}
+
 #[tauri::command(async)]
 pub async fn load_ip_address() -> Result<String, String> {
     match get_address().await {
@@ -458,8 +773,16 @@ pub async fn load_ammeter(app: tauri::AppHandle, ammeter_number: u32) -> Result<
                 .setting
                 .read()
                 .await
-                .write_setting(&app)
+                .write_synced_setting(&app)
                 .map_err(|err| err.to_string())?;
+            events::emit(
+                &app,
+                AppEvent::MeterReadingUpdated {
+                    ammeter_number,
+                    remain: kwh,
+                    average: None,
+                },
+            );
             Ok(kwh.to_string())
         }
         None => Err("获取用电量失败，可能是电表号错误".to_string()),
@@ -478,18 +801,43 @@ pub async fn load_electric_bill(app: tauri::AppHandle) -> Result<String, String>
     let file_path = get_store_path(&app)
         .map_err(|e| e.to_string())?
         .join(format!("{}.json", ammeter_number));
-    let res = update_ammeter(ammeter_number, file_path)
+    let (series, message, updated) = update_ammeter(ammeter_number, file_path)
         .await
         .map_err(|e| e.to_string())?;
-    Ok(serde_json::json!(res).to_string())
+    if updated && let Some(last) = series.last() {
+        events::emit(
+            &app,
+            AppEvent::MeterReadingUpdated {
+                ammeter_number,
+                remain: last.remain,
+                average: Some(last.average),
+            },
+        );
+    }
+    Ok(serde_json::json!((series, message)).to_string())
 }
 
-#[tauri::command(async)]
-pub async fn submit_login_ustb_wifi(user_name: String, password: String) -> Result<String, String> {
+// 按设置选择登录用的网卡，没有设置时不绑定
+async fn login_binding(
+    app_state: &tauri::State<'_, AppState>,
+) -> Result<Option<LoginBinding>, String> {
+    let Some(selected) = app_state.setting.read().await.login_interface.clone() else {
+        return Ok(None);
+    };
+    let interfaces =
+        list_interfaces().map_err(|e| format!("读取本机网卡失败，无法绑定 {selected}：{e}"))?;
+    pick_login_binding(&interfaces, Some(&selected)).map_err(|e| e.to_string())
+}
+
+async fn login_with_retry(
+    user_name: &str,
+    password: &str,
+    binding: Option<LoginBinding>,
+) -> Result<String, String> {
     // 尝试 10 次登录
     let mut err = String::new();
     for _ in 0..10 {
-        match login_ustb_wifi(&user_name, &password).await {
+        match login_ustb_wifi(user_name, password, binding.as_ref()).await {
             Ok(()) => return Ok("登录成功".to_string()),
             Err(e) => err = e.to_string(),
         }
@@ -502,6 +850,16 @@ pub async fn submit_login_ustb_wifi(user_name: String, password: String) -> Resu
     Err(err)
 }
 
+#[tauri::command(async)]
+pub async fn submit_login_ustb_wifi(
+    app_state: tauri::State<'_, AppState>,
+    user_name: String,
+    password: String,
+) -> Result<String, String> {
+    let binding = login_binding(&app_state).await?;
+    login_with_retry(&user_name, &password, binding).await
+}
+
 #[tauri::command]
 pub async fn return_os_type() -> i32 {
     #[allow(unused_assignments)]
@@ -534,36 +892,105 @@ pub async fn collapse(
 }
 
 #[tauri::command(async)]
-pub async fn get_ip_location(ip: String) -> Result<String, String> {
-    if let Err(e) = ip.parse::<IpAddr>() {
-        return Err(format!("IP 格式错误：{e:?}"));
+pub async fn get_ip_location(ip: String, online: Option<bool>) -> Result<String, String> {
+    let ip = ip
+        .trim()
+        .parse::<IpAddr>()
+        .map_err(|e| format!("IP 格式错误：{e:?}"))?;
+
+    let mut location = ip_location::lookup(ip);
+    if matches!(location.source, LocationSource::Unknown) && online.unwrap_or(false) {
+        let text = get_remote_ip_location(&location.ip)
+            .await
+            .map_err(|e| e.to_string())?;
+        location.merge_remote(&text).map_err(|e| e.to_string())?;
     }
 
-    let response = Client::new()
-        .get(format!("https://api.mir6.com/api/ip_json?ip={}", ip))
-        .send()
+    Ok(serde_json::json!(location).to_string())
+}
+
+#[tauri::command(async)]
+pub async fn translate_up(
+    app_state: tauri::State<'_, AppState>,
+    raw_url: String,
+) -> Result<String, String> {
+    app_state
+        .setting
+        .read()
         .await
-        .map_err(|e| e.to_string())?;
+        .current_webvpn_profile()
+        .translate_up(&raw_url)
+        .map_err(|e| format!("转换失败：{}", e))
+}
 
-    let text = response.text().await.map_err(|e| e.to_string())?;
+#[tauri::command(async)]
+pub async fn translate_down(
+    app_state: tauri::State<'_, AppState>,
+    vpn_url: String,
+) -> Result<String, String> {
+    app_state
+        .setting
+        .read()
+        .await
+        .current_webvpn_profile()
+        .translate_down(&vpn_url)
+        .map_err(|e| format!("转换失败：{}", e))
+}
 
-    Ok(text)
+#[tauri::command(async)]
+pub async fn save_webvpn_profile(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    profile: WebVpnProfile,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .set_webvpn_profile(profile)
+        .map_err(|err| err.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|err| err.to_string())
 }
 
-#[tauri::command]
-pub fn translate_up(raw_url: String) -> String {
-    match webvpn::translate_up(&raw_url) {
-        Ok(res) => res,
-        Err(e) => e.to_string(),
-    }
+#[tauri::command(async)]
+pub async fn remove_webvpn_profile(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<(), String> {
+    app_state.setting.write().await.remove_webvpn_profile(&name);
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|err| err.to_string())
 }
 
-#[tauri::command]
-pub fn translate_down(vpn_url: String) -> String {
-    match webvpn::translate_down(&vpn_url) {
-        Ok(res) => res,
-        Err(e) => e.to_string(),
-    }
+// 下次通过 WebVPN 登录时生效，name 为 None 时使用默认的北科 elib
+#[tauri::command(async)]
+pub async fn select_webvpn_profile(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    name: Option<String>,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .select_webvpn_profile(name)
+        .map_err(|err| err.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_synced_setting(&app)
+        .map_err(|err| err.to_string())
 }
 
 #[tauri::command(async)]
@@ -572,61 +999,33 @@ pub async fn switch_login_ustb_wifi(
     user_name: String,
     password: String,
 ) -> Result<String, String> {
-    // 获取本机 mac 地址
-    use if_addrs::Interface;
-    let ifaces = if_addrs::get_if_addrs()
-        .map_err(|e| e.to_string())?
-        .into_iter()
-        .filter_map(|iface| match iface.addr {
-            if_addrs::IfAddr::V4(_) if !iface.is_loopback() => Some(iface),
-            _ => None,
-        })
-        .collect::<Vec<Interface>>();
-
-    let cur_device_macs: HashSet<String> = ifaces
-        .into_iter()
-        .map(|iface| {
-            mac_address::mac_address_by_name(&iface.name)
-                .unwrap_or_default()
-                .unwrap_or_default()
-                .to_string()
-                .replace(':', "")
-        })
-        .collect();
-
-    // 获取该账号校园网记住的 mac 地址
+    // 获取该账号校园网记住的 mac 地址，找到当前设备上已经绑定的
     let cookie_str = get_cookie_str(&app_state).await?;
-    let user_type = *app_state.user_type.read().await;
+    let user_type = app_state.user_type.read().await.clone();
 
-    let (macs, ajax_csrf_token) = get_mac_address(&cookie_str, user_type)
+    let (macs, ajax_csrf_token) = get_mac_address(&cookie_str, &user_type)
         .await
         .map_err(|e| e.to_string())?;
-
-    let macs = macs
-        .rows
+    let local = current_device_macs().map_err(|e| e.to_string())?;
+    let diff_macs = BoundDevice::from_list(&macs, &local)
         .into_iter()
-        .map(|values| values.get(1).map(|e| e.to_string()).unwrap_or_default())
-        .collect::<HashSet<String>>();
-
-    // 取交集 - 找到当前设备上已经绑定的 MAC 地址
-    let diff_macs = cur_device_macs
-        .intersection(&macs)
-        .cloned()
+        .filter(|device| device.this_device.is_some())
+        .map(|device| device.mac)
         .collect::<Vec<String>>();
-    // dbg!(&diff_macs);
 
     if diff_macs.len() != 1 {
         return Err("无法匹配 MAC 地址，请确认当前账号是否已经在这台设备登录了。".to_string());
     }
 
-    match unbind_mac(&cookie_str, user_type, &diff_macs[0], &ajax_csrf_token).await {
+    match unbind_mac(&cookie_str, &user_type, &diff_macs[0], &ajax_csrf_token).await {
         Ok(()) => Ok(()),
         Err(e) => Err(format!("Request Error，检查是否在校园网内: {}", e)),
     }?;
 
     // 登录新账号
+    let binding = login_binding(&app_state).await?;
     tokio::time::sleep(Duration::from_millis(200)).await;
-    submit_login_ustb_wifi(user_name, password).await
+    login_with_retry(&user_name, &password, binding).await
 }
 
 #[tauri::command(async)]
@@ -642,7 +1041,7 @@ pub async fn set_current_user_name(
     user_name: String,
 ) -> Result<(), String> {
     match *app_state.user_type.read().await {
-        UserType::ViaVpn | UserType::Normal => {
+        UserType::ViaVpn(_) | UserType::Normal => {
             *app_state.cur_account.write().await = user_name;
         }
     }
@@ -0,0 +1,425 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    entities::{AppState, MacList, UserType},
+    requests::{get_mac_address, unbind_mac, update_terminal_name},
+    setting::device_alias::find_alias,
+    utils::{
+        get_cookie_str,
+        oui::{is_randomized, lookup_vendor, normalize_mac},
+    },
+};
+
+// 校园网后台 getMacList 返回的一行
+// [
+//     "0", // 是否在线
+//     "MACADDRESS",
+//     "#PC",
+//     "2026-03-15 11:23:32", // 最后一次登录
+//     "10.24.18.81", // 最后一次登录的 IP
+//     "否", // 是否是哑终端
+//     "" // 自定义别名
+// ]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoundDevice {
+    pub mac: String, // 后台返回的原样，解绑和改名时要用
+    pub online: bool,
+    pub device_type: String,
+    pub last_login_time: String,
+    pub last_login_ip: String,
+    pub dumb_terminal: bool,
+    pub custom_name: String,   // 校园网后台上的名字
+    pub alias: Option<String>, // 本地记住的名字
+    pub vendor: Option<String>,
+    pub randomized: bool,            // 随机 MAC，换了网络或者过段时间可能会变
+    pub this_device: Option<String>, // 是本机的话，是哪个网卡
+}
+
+impl BoundDevice {
+    fn from_row(row: &[String]) -> Option<Self> {
+        let col = |i: usize| row.get(i).map(|s| s.trim().to_string()).unwrap_or_default();
+        let mac = col(1);
+        if mac.is_empty() {
+            return None;
+        }
+        Some(BoundDevice {
+            online: col(0) == "1",
+            device_type: col(2),
+            last_login_time: col(3),
+            last_login_ip: col(4),
+            dumb_terminal: col(5) == "是",
+            custom_name: col(6),
+            alias: None,
+            vendor: lookup_vendor(&mac).map(str::to_string),
+            randomized: is_randomized(&mac),
+            this_device: None,
+            mac,
+        })
+    }
+
+    pub fn from_list(list: &MacList, local: &[MacAddress]) -> Vec<Self> {
+        let mut devices: Vec<_> = list
+            .rows
+            .iter()
+            .filter_map(|row| Self::from_row(row))
+            .collect();
+        for device in devices.iter_mut() {
+            let mac = normalize_mac(&device.mac);
+            device.this_device = local
+                .iter()
+                .find(|iface| normalize_mac(&iface.mac_address) == mac)
+                .map(|iface| iface.iface_name.clone());
+        }
+        devices
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MacAddress {
+    pub iface_name: String,
+    pub mac_address: String,
+}
+
+// 本机所有有 IPv4 地址的网卡，拿不到 MAC 的跳过
+pub fn current_device_macs() -> Result<Vec<MacAddress>> {
+    let macs = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|iface| matches!(iface.addr, if_addrs::IfAddr::V4(_)) && !iface.is_loopback())
+        .filter_map(|iface| {
+            let mac = mac_address::mac_address_by_name(&iface.name).ok()??;
+            Some(MacAddress {
+                mac_address: mac.to_string().replace(':', ""),
+                iface_name: iface.name,
+            })
+        })
+        .collect();
+    Ok(macs)
+}
+
+// 批量操作中每个 MAC 的结果
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MacResult {
+    pub mac: String,
+    pub success: bool,
+    pub message: Option<String>, // 失败或者拒绝操作的原因
+}
+
+impl MacResult {
+    fn new(mac: String, res: Result<(), String>) -> Self {
+        MacResult {
+            mac,
+            success: res.is_ok(),
+            message: res.err(),
+        }
+    }
+}
+
+// 按传进来的顺序，要解绑的给出后台的 MAC，不能解绑的给出原因
+fn plan_unbind(
+    devices: &[BoundDevice],
+    macs: &[String],
+    force: bool,
+) -> Vec<(String, Result<String, String>)> {
+    let mut seen = HashSet::new();
+    macs.iter()
+        .filter(|mac| seen.insert(normalize_mac(mac)))
+        .map(|mac| {
+            let device = devices
+                .iter()
+                .find(|d| normalize_mac(&d.mac) == normalize_mac(mac));
+            let plan = match device {
+                None => Err("该 MAC 地址没有绑定在当前账号上".to_string()),
+                Some(d) if d.this_device.is_some() && !force => {
+                    Err("这是当前设备，解绑后会立刻断网，需要确认后再解绑".to_string())
+                }
+                Some(d) => Ok(d.mac.clone()),
+            };
+            (mac.clone(), plan)
+        })
+        .collect()
+}
+
+async fn load_devices(
+    cookie_str: &str,
+    user_type: &UserType,
+) -> Result<(Vec<BoundDevice>, String)> {
+    let (list, ajax_csrf_token) = get_mac_address(cookie_str, user_type).await?;
+    let local = current_device_macs().unwrap_or_default();
+    Ok((BoundDevice::from_list(&list, &local), ajax_csrf_token))
+}
+
+// 批量解绑，默认不解绑当前设备，force 为 true 时才解绑
+// 返回每个 MAC 的结果
+#[tauri::command(async)]
+pub async fn unbind_devices(
+    app_state: tauri::State<'_, AppState>,
+    macs: Vec<String>,
+    force: bool,
+) -> Result<String, String> {
+    let cookie_str = get_cookie_str(&app_state).await?;
+    let user_type = app_state.user_type.read().await.clone();
+    let (devices, ajax_csrf_token) = load_devices(&cookie_str, &user_type)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut results = vec![];
+    for (mac, plan) in plan_unbind(&devices, &macs, force) {
+        let res = match plan {
+            Ok(device_mac) => unbind_mac(&cookie_str, &user_type, &device_mac, &ajax_csrf_token)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(reason) => Err(reason),
+        };
+        results.push(MacResult::new(mac, res));
+    }
+
+    // 解绑接口不返回结果，重新拉一次列表确认
+    if results.iter().any(|r| r.success) {
+        let (remaining, _) = load_devices(&cookie_str, &user_type)
+            .await
+            .map_err(|e| e.to_string())?;
+        let remaining: HashSet<_> = remaining.iter().map(|d| normalize_mac(&d.mac)).collect();
+        for r in results.iter_mut().filter(|r| r.success) {
+            if remaining.contains(&normalize_mac(&r.mac)) {
+                r.success = false;
+                r.message = Some("解绑后仍在绑定列表中".to_string());
+            }
+        }
+    }
+    Ok(serde_json::to_string(&results).unwrap_or_default())
+}
+
+// 校园网后台上有名字、本地还没记的设备，记下来，解绑之后还能恢复
+#[tauri::command(async)]
+pub async fn load_bound_devices(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+) -> Result<String, String> {
+    let cookie_str = get_cookie_str(&app_state).await?;
+    let user_type = app_state.user_type.read().await.clone();
+    let (mut devices, ajax_csrf_token) = load_devices(&cookie_str, &user_type)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut setting = app_state.setting.write().await;
+    let mut learned = false;
+    for device in devices.iter_mut() {
+        if !device.custom_name.is_empty()
+            && find_alias(&setting.device_aliases, &device.mac).is_none()
+        {
+            learned |= setting.set_device_alias(&device.mac, &device.custom_name);
+        }
+        device.alias = find_alias(&setting.device_aliases, &device.mac).map(str::to_string);
+    }
+    if learned {
+        setting
+            .write_synced_setting(&app)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(serde_json::json!({
+        "devices": devices,
+        "ajaxCsrfToken": ajax_csrf_token,
+    })
+    .to_string())
+}
+
+// updateTerminalName 没有文档，见过返回 JSON 的也见过返回纯文本的
+// 登录失效时返回的是登录页
+fn parse_rename_response(text: &str) -> Result<()> {
+    let text = text.trim();
+    if text.starts_with('<') {
+        return Err(anyhow!("登录已失效，请重新登录校园网后台"));
+    }
+    match serde_json::from_str::<Value>(text) {
+        Ok(Value::Object(map)) => {
+            let message = map
+                .get("message")
+                .or(map.get("msg"))
+                .and_then(Value::as_str)
+                .filter(|m| !m.is_empty())
+                .unwrap_or("修改失败");
+            match map.get("success").or(map.get("result")) {
+                Some(Value::Bool(false)) => Err(anyhow!("{message}")),
+                Some(Value::String(s)) if !matches!(s.as_str(), "success" | "true" | "ok") => {
+                    Err(anyhow!("{message}"))
+                }
+                _ => Ok(()),
+            }
+        }
+        Ok(Value::Bool(false)) => Err(anyhow!("修改失败")),
+        _ if text.contains("失败") => Err(anyhow!("{text}")),
+        _ => Ok(()),
+    }
+}
+
+async fn rename(
+    cookie_str: &str,
+    user_type: &UserType,
+    mac: &str,
+    name: &str,
+    ajax_csrf_token: &str,
+) -> Result<()> {
+    let text = update_terminal_name(cookie_str, user_type, mac, name, ajax_csrf_token).await?;
+    parse_rename_response(&text)
+}
+
+// 先记到本地再设置到校园网后台，后台失败时本地的名字也保留
+#[tauri::command(async)]
+pub async fn set_mac_custom_name(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    mac_address: String,
+    terminal_name: String,
+    ajax_csrf_token: String,
+) -> Result<(), String> {
+    {
+        let mut setting = app_state.setting.write().await;
+        if setting.set_device_alias(&mac_address, &terminal_name) {
+            setting
+                .write_synced_setting(&app)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    let cookie_str = get_cookie_str(&app_state).await?;
+    let user_type = app_state.user_type.read().await.clone();
+    rename(
+        &cookie_str,
+        &user_type,
+        &mac_address,
+        terminal_name.trim(),
+        &ajax_csrf_token,
+    )
+    .await
+    .map_err(|e| format!("已保存到本地，但没能设置到校园网后台：{e}"))
+}
+
+// 把本地记住的名字重新设置到校园网后台，名字一样的跳过，返回每个 MAC 的结果
+#[tauri::command(async)]
+pub async fn apply_device_aliases(app_state: tauri::State<'_, AppState>) -> Result<String, String> {
+    let cookie_str = get_cookie_str(&app_state).await?;
+    let user_type = app_state.user_type.read().await.clone();
+    let (devices, ajax_csrf_token) = load_devices(&cookie_str, &user_type)
+        .await
+        .map_err(|e| e.to_string())?;
+    let pending: Vec<(String, String)> = {
+        let setting = app_state.setting.read().await;
+        devices
+            .iter()
+            .filter_map(|d| {
+                let alias = find_alias(&setting.device_aliases, &d.mac)?;
+                (alias != d.custom_name).then(|| (d.mac.clone(), alias.to_string()))
+            })
+            .collect()
+    };
+
+    let mut results = vec![];
+    for (mac, name) in &pending {
+        let res = rename(&cookie_str, &user_type, mac, name, &ajax_csrf_token)
+            .await
+            .map_err(|e| e.to_string());
+        results.push(MacResult::new(mac.clone(), res));
+    }
+
+    // 再拉一次列表，确认名字真的改了
+    if results.iter().any(|r| r.success) {
+        let (devices, _) = load_devices(&cookie_str, &user_type)
+            .await
+            .map_err(|e| e.to_string())?;
+        for (r, (_, name)) in results.iter_mut().zip(&pending).filter(|(r, _)| r.success) {
+            let renamed = devices
+                .iter()
+                .any(|d| d.mac == r.mac && &d.custom_name == name);
+            if !renamed {
+                r.success = false;
+                r.message = Some("设置后校园网后台的名字没有变化".to_string());
+            }
+        }
+    }
+    Ok(serde_json::to_string(&results).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(cols: [&str; 7]) -> Vec<String> {
+        cols.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_list() {
+        let list: MacList = serde_json::from_value(serde_json::json!({
+            "total": 3,
+            "rows": [
+                row(["1", "F01898AABBCC", "#PC", "2026-03-15 11:23:32", "10.24.18.81", "否", "laptop"]),
+                row(["0", "DAA119000000", "#PC", "", "", "是", ""]),
+                ["0", ""],
+            ],
+        }))
+        .unwrap();
+        let local = [MacAddress {
+            iface_name: "en0".to_string(),
+            mac_address: "f0:18:98:aa:bb:cc".to_string(),
+        }];
+        let devices = BoundDevice::from_list(&list, &local);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices[0],
+            BoundDevice {
+                mac: "F01898AABBCC".to_string(),
+                online: true,
+                device_type: "#PC".to_string(),
+                last_login_time: "2026-03-15 11:23:32".to_string(),
+                last_login_ip: "10.24.18.81".to_string(),
+                dumb_terminal: false,
+                custom_name: "laptop".to_string(),
+                alias: None,
+                vendor: Some("Apple".to_string()),
+                randomized: false,
+                this_device: Some("en0".to_string()),
+            }
+        );
+        assert!(devices[1].randomized && devices[1].dumb_terminal);
+        assert_eq!(devices[1].this_device, None);
+
+        let macs = [
+            "F0:18:98:AA:BB:CC",
+            "DAA119000000",
+            "daa119000000",
+            "000000000001",
+        ]
+        .map(str::to_string);
+        let plan = plan_unbind(&devices, &macs, false);
+        assert_eq!(plan.len(), 3);
+        assert!(plan[0].1.is_err());
+        assert_eq!(plan[1].1, Ok("DAA119000000".to_string()));
+        assert!(plan[2].1.is_err());
+        let plan = plan_unbind(&devices, &macs, true);
+        assert_eq!(plan[0].1, Ok("F01898AABBCC".to_string()));
+    }
+
+    #[test]
+    fn test_parse_rename_response() {
+        assert!(parse_rename_response(r#"{"success": true, "message": ""}"#).is_ok());
+        assert_eq!(
+            parse_rename_response(r#"{"success": false, "message": "名称过长"}"#)
+                .unwrap_err()
+                .to_string(),
+            "名称过长"
+        );
+        assert!(parse_rename_response(r#"{"result": "fail"}"#).is_err());
+        assert!(parse_rename_response("success").is_ok());
+        assert!(parse_rename_response("修改失败").is_err());
+        assert_eq!(
+            parse_rename_response("<!DOCTYPE html><html></html>")
+                .unwrap_err()
+                .to_string(),
+            "登录已失效，请重新登录校园网后台"
+        );
+    }
+}
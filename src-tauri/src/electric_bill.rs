@@ -1,17 +1,22 @@
 use std::{io::SeekFrom, path::PathBuf};
 
-use crate::{entities::RemainingElectricity, requests::get_ammeter};
-use anyhow::{anyhow, Result};
-use chrono::{Days, Local, TimeZone};
+use crate::{
+    entities::RemainingElectricity,
+    requests::get_ammeter,
+    utils::{campus_date, campus_today},
+};
+use anyhow::{Result, anyhow};
+use chrono::{Days, NaiveDate, Utc};
 use tokio::{
     fs::OpenOptions,
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
 
+// 今天还没取过就取一次追加到记录里，返回全部记录、提示和这次有没有新数据
 pub async fn update_ammeter(
     ammeter_number: u32,
     file_path: PathBuf,
-) -> Result<(Vec<RemainingElectricity>, String)> {
+) -> Result<(Vec<RemainingElectricity>, String, bool)> {
     let mut buf = vec![];
     let mut file = OpenOptions::new()
         .create(true)
@@ -25,22 +30,19 @@ pub async fn update_ammeter(
         remain_elec = serde_json::from_slice::<Vec<RemainingElectricity>>(&buf)?;
         // dbg!(&remain_elec);
     }
-    let now = Local::now();
-    let mut last_data_day = now.date_naive().checked_sub_days(Days::new(1)).unwrap();
+    // 按北京时间算哪一天，换了系统时区也不会一天取两次
+    let today = campus_today();
+    let mut last_data_day = today.checked_sub_days(Days::new(1)).unwrap();
     let mut last_remain = 0;
     // 判断最后获取日期是否是今天，如果是，不用再获取了
     if let Some(last_data) = remain_elec.last() {
-        let today = now.date_naive();
         last_remain = last_data.remain;
-        last_data_day = now
-            .offset()
-            .timestamp_opt(last_data.date, 0)
-            .unwrap()
-            .date_naive();
+        last_data_day = campus_date(last_data.date).ok_or(anyhow!("电费记录的日期不对"))?;
         if today == last_data_day {
             return Ok((
                 remain_elec,
                 "今日已经获取过电表数据，明天再来吧".to_string(),
+                false,
             ));
         }
     }
@@ -50,10 +52,9 @@ pub async fn update_ammeter(
             .await?
             .ok_or(anyhow!("获取今日数据时返回为 None"))?;
         RemainingElectricity {
-            date: now.timestamp(),
+            date: Utc::now().timestamp(),
             remain,
-            average: (last_remain - remain) as f64
-                / (now.date_naive() - last_data_day).num_days() as f64,
+            average: (last_remain - remain) as f64 / (today - last_data_day).num_days() as f64,
         }
     };
     remain_elec.push(new_data);
@@ -62,14 +63,43 @@ pub async fn update_ammeter(
     file.set_len(0).await?;
     file.seek(SeekFrom::Start(0)).await?;
     let _ = file.write_all(&serde_json::to_vec(&remain_elec)?).await;
-    Ok((remain_elec, "已更新今日数据".to_string()))
+    Ok((remain_elec, "已更新今日数据".to_string(), true))
+}
+
+// 合并两台设备上的记录，按时间排序，同一天只保留最早的一条，再重新计算日均用电
+pub fn merge_series(
+    local: &[RemainingElectricity],
+    remote: &[RemainingElectricity],
+) -> Vec<RemainingElectricity> {
+    let mut all: Vec<_> = local.iter().chain(remote).cloned().collect();
+    all.sort_by_key(|item| item.date);
+
+    let mut res: Vec<RemainingElectricity> = vec![];
+    let mut last: Option<(NaiveDate, i32)> = None;
+    for mut item in all {
+        let Some(day) = campus_date(item.date) else {
+            continue;
+        };
+        if let Some((last_day, last_remain)) = last {
+            if last_day == day {
+                continue;
+            }
+            item.average = (last_remain - item.remain) as f64 / (day - last_day).num_days() as f64;
+        }
+        last = Some((day, item.remain));
+        res.push(item);
+    }
+    res
 }
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
 
-    use crate::electric_bill::update_ammeter;
+    use crate::{
+        electric_bill::{merge_series, update_ammeter},
+        entities::RemainingElectricity,
+    };
 
     #[tokio::test]
     async fn test_update_ammeter() {
@@ -78,4 +108,27 @@ mod tests {
         let res = update_ammeter(ammeter_number, path).await;
         dbg!(res.unwrap());
     }
+
+    #[test]
+    fn test_merge_series() {
+        const DAY: i64 = 24 * 60 * 60;
+        let item = |day: i64, remain: i32| RemainingElectricity {
+            date: 1_740_800_000 + day * DAY,
+            remain,
+            average: 0.0,
+        };
+        let local = vec![item(0, 100), item(1, 90), item(4, 60)];
+        // 第 1 天两边都取过，远端的晚一点
+        let mut remote = vec![item(1, 89), item(2, 80)];
+        remote[0].date += 60;
+
+        let res = merge_series(&local, &remote);
+        assert_eq!(
+            res.iter().map(|r| r.remain).collect::<Vec<_>>(),
+            vec![100, 90, 80, 60]
+        );
+        assert_eq!(res[1].average, 10.0);
+        assert_eq!(res[3].average, 10.0);
+        assert_eq!(merge_series(&res, &res), res);
+    }
 }
@@ -1,7 +1,11 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
-use crate::setting::Setting;
+use crate::{
+    events::ObservedState, kick::SessionActivity, setting::Setting, utils::webvpn::WebVpnProfile,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Account {
@@ -16,11 +20,18 @@ pub struct MacList {
     pub rows: Vec<Vec<String>>,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone)]
 pub enum UserType {
     #[default]
     Normal,
-    ViaVpn,
+    ViaVpn(WebVpnProfile),
+}
+
+// 一个账号登录校园网后台后的会话
+#[derive(Clone)]
+pub struct UserSession {
+    pub cookie_str: String,
+    pub user_type: UserType,
 }
 
 #[derive(Default)]
@@ -29,7 +40,10 @@ pub struct AppState {
     pub cur_account: RwLock<String>,
     pub setting: RwLock<Setting>,
     pub user_type: RwLock<UserType>,
-    pub onedrive_code_verifier: RwLock<Option<String>>, 
+    pub sessions: RwLock<HashMap<String, UserSession>>, // 学号 -> 会话，上面两个是当前使用的那个
+    pub webvpn_proxy: RwLock<Option<tauri::async_runtime::JoinHandle<()>>>,
+    pub session_activity: RwLock<SessionActivity>, // 自动下线规则判断空闲用
+    pub observed: RwLock<ObservedState>,           // 上次看到的余额和在线设备，发事件用
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,7 +56,7 @@ pub struct AmmeterData {
     pub status_code: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RemainingElectricity {
     pub date: i64,
     pub remain: i32,
@@ -62,3 +76,17 @@ pub enum DownloadEvent {
     #[serde(rename_all = "camelCase")]
     Finished { finished: bool },
 }
+
+// 分段获取上网明细的进度，chunks 是按月切开的区间数
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+pub enum OnlineLogEvent {
+    #[serde(rename_all = "camelCase")]
+    Started { chunks: usize },
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        finished_chunks: usize,
+        chunks: usize,
+        rows: usize,
+    },
+}
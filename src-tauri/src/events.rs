@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{Emitter, Manager};
+
+use crate::{
+    entities::AppState,
+    kick::{KickReport, OnlineSession},
+    report::{ReportFailed, ReportGenerated},
+    requests::{get_online_list, get_user_dashboard},
+    setting::device_alias::{DeviceAlias, find_alias},
+    utils::oui::normalize_mac,
+};
+
+// 后台状态变化都通过这一个事件发给前端，前端和以后的托盘菜单订阅它就行，不用反复调用命令
+pub const APP_EVENT: &str = "app_event";
+// 后台查余额和在线设备的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+pub enum AppEvent {
+    // 登录或登出校园网后台
+    #[serde(rename_all = "camelCase")]
+    LoginStateChanged {
+        user_name: String,
+        logged_in: bool,
+    },
+    // 发现会话已经失效，需要重新登录
+    #[serde(rename_all = "camelCase")]
+    SessionExpired {
+        user_name: String,
+    },
+    // 余额，单位元，第一次查到时 old_balance 为空
+    #[serde(rename_all = "camelCase")]
+    BalanceChanged {
+        user_name: String,
+        old_balance: Option<f64>,
+        balance: f64,
+    },
+    // 在线列表里出现了上次没有的设备
+    #[serde(rename_all = "camelCase")]
+    NewDeviceOnline {
+        user_name: String,
+        mac: String,
+        ip: String,
+        name: Option<String>, // 本地记住的设备名，没有的话用主机名
+    },
+    // 电表剩余电量，average 是和上一次记录相比的日均用电，实时查询时为空
+    #[serde(rename_all = "camelCase")]
+    MeterReadingUpdated {
+        ammeter_number: u32,
+        remain: i32,
+        average: Option<f64>,
+    },
+    // automatic 表示是后台的自动同步，手动同步的结果命令里已经返回了
+    #[serde(rename_all = "camelCase")]
+    SyncFinished {
+        message: String,
+        error: Option<String>,
+        automatic: bool,
+    },
+    // 后台按自动下线规则检查完一次
+    KickReport(KickReport),
+    // 后台按自动下线规则检查时出错，time 是秒
+    #[serde(rename_all = "camelCase")]
+    KickFailed {
+        time: i64,
+        error: String,
+    },
+    // 定时生成了使用报告
+    ReportGenerated(ReportGenerated),
+    // 定时生成使用报告失败，会退避重试
+    ReportFailed(ReportFailed),
+}
+
+// 上次看到的余额和在线设备，用来判断有没有变化
+#[derive(Debug, Default)]
+pub struct ObservedState {
+    balances: HashMap<String, f64>,
+    online_macs: HashMap<String, HashSet<String>>,
+}
+
+impl ObservedState {
+    fn balance(&mut self, user_name: &str, balance: f64) -> Option<AppEvent> {
+        let old_balance = self.balances.insert(user_name.to_string(), balance);
+        (old_balance != Some(balance)).then(|| AppEvent::BalanceChanged {
+            user_name: user_name.to_string(),
+            old_balance,
+            balance,
+        })
+    }
+
+    // 第一次看到某个账号的在线列表时只记下来，之后新出现的 MAC 才算新设备上线
+    fn online(
+        &mut self,
+        user_name: &str,
+        sessions: &[OnlineSession],
+        aliases: &[DeviceAlias],
+    ) -> Vec<AppEvent> {
+        let macs: HashSet<String> = sessions.iter().map(|s| normalize_mac(&s.mac)).collect();
+        let Some(old) = self.online_macs.insert(user_name.to_string(), macs) else {
+            return vec![];
+        };
+        let mut seen = HashSet::new();
+        sessions
+            .iter()
+            .filter_map(|s| {
+                let mac = normalize_mac(&s.mac);
+                if old.contains(&mac) || !seen.insert(mac.clone()) {
+                    return None;
+                }
+                let name = find_alias(aliases, &mac)
+                    .map(str::to_string)
+                    .or((!s.host_name.is_empty()).then(|| s.host_name.clone()));
+                Some(AppEvent::NewDeviceOnline {
+                    user_name: user_name.to_string(),
+                    mac,
+                    ip: s.ip.clone(),
+                    name,
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn emit(app: &tauri::AppHandle, event: AppEvent) {
+    let _ = app.emit(APP_EVENT, event);
+}
+
+pub async fn observe_balance(app: &tauri::AppHandle, user_name: &str, balance: f64) {
+    let app_state = app.state::<AppState>();
+    let event = app_state.observed.write().await.balance(user_name, balance);
+    if let Some(event) = event {
+        emit(app, event);
+    }
+}
+
+// 拿到 dashboard 之后调用，余额有变化就发事件
+pub async fn observe_dashboard(app: &tauri::AppHandle, user_name: &str, dashboard: &str) {
+    let balance = serde_json::from_str::<Value>(dashboard)
+        .ok()
+        .and_then(|d| d["leftMoney"].as_f64());
+    if let Some(balance) = balance {
+        observe_balance(app, user_name, balance).await;
+    }
+}
+
+pub async fn observe_online(app: &tauri::AppHandle, user_name: &str, sessions: &[OnlineSession]) {
+    let app_state = app.state::<AppState>();
+    let aliases = app_state.setting.read().await.device_aliases.clone();
+    let events = app_state
+        .observed
+        .write()
+        .await
+        .online(user_name, sessions, &aliases);
+    for event in events {
+        emit(app, event);
+    }
+}
+
+// 启动后定时查当前账号的余额和在线设备，有变化时发事件，不用等用户打开页面
+// 没登录时跳过，请求失败（比如不在校园网里）时等下次再查
+pub async fn poll_task(app: tauri::AppHandle) {
+    // 会话失效只提示一次，重新登录换了 cookie 再接着查
+    let mut expired_cookie: Option<String> = None;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let app_state = app.state::<AppState>();
+        let Some(cookie_str) = app_state.cookie_str.read().await.clone() else {
+            continue;
+        };
+        if expired_cookie.as_ref() == Some(&cookie_str) {
+            continue;
+        }
+        let user_type = app_state.user_type.read().await.clone();
+        let user_name = app_state.cur_account.read().await.clone();
+        match get_user_dashboard(&cookie_str, &user_type).await {
+            Ok(Some(dashboard)) => observe_dashboard(&app, &user_name, &dashboard).await,
+            Ok(None) => {
+                emit(&app, AppEvent::SessionExpired { user_name });
+                expired_cookie = Some(cookie_str);
+                continue;
+            }
+            Err(_) => continue,
+        }
+        if let Ok(json_str) = get_online_list(&cookie_str, &user_type).await
+            && let Ok(sessions) = serde_json::from_str::<Vec<OnlineSession>>(&json_str)
+        {
+            observe_online(&app, &user_name, &sessions).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(mac: &str, ip: &str, host_name: &str) -> OnlineSession {
+        OnlineSession {
+            mac: mac.to_string(),
+            ip: ip.to_string(),
+            host_name: host_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_observed_state() {
+        let mut observed = ObservedState::default();
+        assert_eq!(
+            observed.balance("u1", 20.0),
+            Some(AppEvent::BalanceChanged {
+                user_name: "u1".to_string(),
+                old_balance: None,
+                balance: 20.0
+            })
+        );
+        assert_eq!(observed.balance("u1", 20.0), None);
+        assert!(matches!(
+            observed.balance("u1", 18.5),
+            Some(AppEvent::BalanceChanged {
+                old_balance: Some(20.0),
+                ..
+            })
+        ));
+
+        let aliases = [DeviceAlias {
+            mac: "AABBCC000002".to_string(),
+            name: "平板".to_string(),
+        }];
+        let first = [session("aa:bb:cc:00:00:01", "10.0.0.1", "laptop")];
+        assert!(observed.online("u1", &first, &aliases).is_empty());
+        let second = [
+            session("AABBCC000001", "10.0.0.1", "laptop"),
+            session("aa-bb-cc-00-00-02", "10.0.0.2", "ipad"),
+            session("aa-bb-cc-00-00-02", "2001:da8::2", "ipad"),
+            session("aa-bb-cc-00-00-03", "10.0.0.3", ""),
+        ];
+        assert_eq!(
+            observed.online("u1", &second, &aliases),
+            [
+                AppEvent::NewDeviceOnline {
+                    user_name: "u1".to_string(),
+                    mac: "AABBCC000002".to_string(),
+                    ip: "10.0.0.2".to_string(),
+                    name: Some("平板".to_string()),
+                },
+                AppEvent::NewDeviceOnline {
+                    user_name: "u1".to_string(),
+                    mac: "AABBCC000003".to_string(),
+                    ip: "10.0.0.3".to_string(),
+                    name: None,
+                },
+            ]
+        );
+        assert!(observed.online("u1", &second, &aliases).is_empty());
+        // 别的账号第一次看到也只记下来
+        assert!(observed.online("u2", &second, &aliases).is_empty());
+    }
+
+    #[test]
+    fn test_event_json() {
+        // 前端 events.ts 按这个格式解析
+        let event = AppEvent::ReportGenerated(ReportGenerated {
+            user_name: "u1".to_string(),
+            title: "2025 年 5 月".to_string(),
+            path: "/tmp/u1.md".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event": "reportGenerated",
+                "data": {"userName": "u1", "title": "2025 年 5 月", "path": "/tmp/u1.md"},
+            })
+        );
+        let event = AppEvent::KickFailed {
+            time: 1,
+            error: "x".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"event": "kickFailed", "data": {"time": 1, "error": "x"}})
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Result, anyhow};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::{
+    devices::current_device_macs,
+    entities::AppState,
+    events::{self, AppEvent},
+    requests::{get_online_list, to_offline},
+    setting::kick_policy::{KickPolicy, KickRule, MIN_INTERVAL_MINUTES},
+    utils::{number_or_string, oui::normalize_mac},
+};
+
+// getOnlineList 返回的一项，只取用得到的字段
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OnlineSession {
+    pub session_id: String,
+    pub mac: String,
+    pub ip: String,
+    pub host_name: String,
+    #[serde(deserialize_with = "number_or_string")]
+    pub up_flow: f64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub down_flow: f64,
+}
+
+// 记录每个会话流量上次变化的时间，后台没有空闲时间，只能自己数
+#[derive(Debug, Default)]
+pub struct SessionActivity(HashMap<String, (f64, i64)>);
+
+impl SessionActivity {
+    fn observe(&mut self, sessions: &[OnlineSession], now: i64) {
+        let mut next = HashMap::new();
+        for s in sessions {
+            let flow = s.up_flow + s.down_flow;
+            let since = match self.0.get(&s.session_id) {
+                Some(&(last_flow, since)) if last_flow == flow => since,
+                _ => now,
+            };
+            next.insert(s.session_id.clone(), (flow, since));
+        }
+        // 已经下线的会话不用再记
+        self.0 = next;
+    }
+
+    fn idle_secs(&self, session_id: &str, now: i64) -> i64 {
+        self.0.get(session_id).map_or(0, |&(_, since)| now - since)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KickDecision {
+    pub session_id: String,
+    pub mac: String,
+    pub ip: String,
+    pub host_name: String,
+    pub kick: bool,
+    pub reason: Option<String>, // 为什么下线，或者为什么保留
+    pub error: Option<String>,  // 下线失败的原因
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KickReport {
+    pub dry_run: bool,
+    pub time: i64,
+    pub decisions: Vec<KickDecision>,
+}
+
+// 保留当前设备的规则优先，其余规则满足任意一条就下线
+fn evaluate(
+    policy: &KickPolicy,
+    sessions: &[OnlineSession],
+    this_macs: &[String],
+    activity: &SessionActivity,
+    now: i64,
+) -> Vec<KickDecision> {
+    let keep_this_device = policy.rules.contains(&KickRule::KeepThisDevice);
+    sessions
+        .iter()
+        .map(|s| {
+            let mac = normalize_mac(&s.mac);
+            let (kick, reason) = if keep_this_device && this_macs.contains(&mac) {
+                (false, Some("当前设备，始终保留".to_string()))
+            } else {
+                let reason = policy.rules.iter().find_map(|rule| match rule {
+                    KickRule::KeepThisDevice => None,
+                    KickRule::KickNotInList { list } => policy
+                        .device_list(list)
+                        .filter(|l| !l.macs.iter().any(|m| normalize_mac(m) == mac))
+                        .map(|_| format!("不在设备名单「{list}」中")),
+                    KickRule::KickIdle { hours } => {
+                        let idle = activity.idle_secs(&s.session_id, now);
+                        (idle >= *hours as i64 * 3600)
+                            .then(|| format!("已经 {} 小时没有流量", idle / 3600))
+                    }
+                });
+                (reason.is_some(), reason)
+            };
+            KickDecision {
+                session_id: s.session_id.clone(),
+                mac: s.mac.clone(),
+                ip: s.ip.clone(),
+                host_name: s.host_name.clone(),
+                kick,
+                reason,
+                error: None,
+            }
+        })
+        .collect()
+}
+
+// 对当前登录的账号按规则检查一遍，dry_run 时只报告不下线
+pub async fn run_policy(app: &tauri::AppHandle, dry_run: bool) -> Result<KickReport> {
+    let app_state = app.state::<AppState>();
+    let policy = app_state.setting.read().await.kick_policy.clone();
+    let cookie_str = app_state
+        .cookie_str
+        .read()
+        .await
+        .clone()
+        .ok_or(anyhow!("还没有登录校园网后台"))?;
+    let user_type = app_state.user_type.read().await.clone();
+    let user_name = app_state.cur_account.read().await.clone();
+
+    // 认不出本机的话“保留本机”就不起作用了，宁可这次不执行
+    let this_macs: Vec<String> = current_device_macs()
+        .map_err(|e| anyhow!("读取本机网卡地址失败，本次不执行：{e}"))?
+        .iter()
+        .map(|m| normalize_mac(&m.mac_address))
+        .collect();
+
+    let json_str = get_online_list(&cookie_str, &user_type).await?;
+    let Ok(sessions) = serde_json::from_str::<Vec<OnlineSession>>(&json_str) else {
+        events::emit(app, AppEvent::SessionExpired { user_name });
+        return Err(anyhow!("获取在线设备失败，可能是登录已失效"));
+    };
+    events::observe_online(app, &user_name, &sessions).await;
+    let now = Local::now().timestamp();
+    let mut decisions = {
+        let mut activity = app_state.session_activity.write().await;
+        activity.observe(&sessions, now);
+        evaluate(&policy, &sessions, &this_macs, &activity, now)
+    };
+
+    if !dry_run {
+        for d in decisions.iter_mut().filter(|d| d.kick) {
+            if let Err(e) = to_offline(&cookie_str, &user_type, &d.session_id).await {
+                d.error = Some(e.to_string());
+            }
+        }
+    }
+    Ok(KickReport {
+        dry_run,
+        time: now,
+        decisions,
+    })
+}
+
+// 启动时开始，按配置的间隔检查，没开启或者没登录时跳过
+// 结果通过 KickReport 事件发给前端，出错时发 KickFailed
+pub async fn kick_task(app: tauri::AppHandle) {
+    loop {
+        let interval = app
+            .state::<AppState>()
+            .setting
+            .read()
+            .await
+            .kick_policy
+            .interval_minutes
+            .max(MIN_INTERVAL_MINUTES);
+        tokio::time::sleep(Duration::from_secs(interval as u64 * 60)).await;
+
+        let app_state = app.state::<AppState>();
+        let policy = app_state.setting.read().await.kick_policy.clone();
+        if !policy.enabled || app_state.cookie_str.read().await.is_none() {
+            continue;
+        }
+        match run_policy(&app, policy.dry_run).await {
+            Ok(report) => events::emit(&app, AppEvent::KickReport(report)),
+            Err(e) => events::emit(
+                &app,
+                AppEvent::KickFailed {
+                    time: Local::now().timestamp(),
+                    error: e.to_string(),
+                },
+            ),
+        }
+    }
+}
+
+#[tauri::command(async)]
+pub async fn set_kick_policy(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    policy: KickPolicy,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .set_kick_policy(policy)
+        .map_err(|e| e.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+// 立即按规则检查一次，返回报告
+#[tauri::command(async)]
+pub async fn run_kick_policy(app: tauri::AppHandle, dry_run: bool) -> Result<String, String> {
+    let report = run_policy(&app, dry_run).await.map_err(|e| e.to_string())?;
+    Ok(serde_json::to_string(&report).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setting::kick_policy::DeviceList;
+
+    fn session(id: &str, mac: &str, down_flow: &str) -> OnlineSession {
+        serde_json::from_value(serde_json::json!({
+            "sessionId": id,
+            "mac": mac,
+            "downFlow": down_flow,
+            "upFlow": 1,
+            "userId": 1,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        const HOUR: i64 = 3600;
+        let policy = KickPolicy {
+            rules: vec![
+                KickRule::KeepThisDevice,
+                KickRule::KickNotInList {
+                    list: "mine".to_string(),
+                },
+                KickRule::KickIdle { hours: 2 },
+            ],
+            device_lists: vec![DeviceList {
+                name: "mine".to_string(),
+                macs: vec!["aa:bb:cc:00:00:01".to_string(), "AABBCC000002".to_string()],
+            }],
+            ..Default::default()
+        };
+        let this_macs = vec!["AABBCC000009".to_string()];
+        let mut activity = SessionActivity::default();
+
+        let sessions = vec![
+            session("1", "AABBCC000001", "100"),
+            session("2", "AABBCC000002", "100"),
+            session("3", "112233445566", "100"),
+            session("9", "AABBCC000009", "100"),
+        ];
+        activity.observe(&sessions, 0);
+        let sessions = vec![
+            session("1", "AABBCC000001", "200"),
+            session("2", "AABBCC000002", "100"),
+            session("3", "112233445566", "100"),
+            session("9", "AABBCC000009", "100"),
+        ];
+        activity.observe(&sessions, 3 * HOUR);
+        let res = evaluate(&policy, &sessions, &this_macs, &activity, 3 * HOUR);
+        let kicks: Vec<_> = res.iter().map(|d| d.kick).collect();
+        assert_eq!(kicks, [false, true, true, false]);
+        assert_eq!(res[1].reason.as_deref(), Some("已经 3 小时没有流量"));
+        assert_eq!(res[2].reason.as_deref(), Some("不在设备名单「mine」中"));
+        assert_eq!(res[3].reason.as_deref(), Some("当前设备，始终保留"));
+        assert_eq!(res[0].reason, None);
+
+        // 没有规则的时候谁都不下线
+        let res = evaluate(
+            &KickPolicy {
+                rules: vec![],
+                ..Default::default()
+            },
+            &sessions,
+            &this_macs,
+            &activity,
+            3 * HOUR,
+        );
+        assert!(res.iter().all(|d| !d.kick));
+    }
+}
@@ -1,14 +1,35 @@
+pub mod accounts;
+pub mod analytics;
+pub mod backup;
 pub mod commands;
+pub mod devices;
+pub mod electric_bill;
 pub mod entities;
+pub mod events;
+pub mod kick;
 pub mod onedrive;
+pub mod proxy;
+pub mod report;
 mod requests;
+pub mod session;
 pub mod setting;
+pub mod sync;
+pub mod usage;
 pub mod utils;
-pub mod electric_bill;
 
 use crate::commands::*;
 use crate::entities::AppState;
-use onedrive::open_microsoft_login;
+use accounts::{
+    forget_all_credentials, load_accounts_summary, remove_account, set_account_preferences,
+    switch_session, update_account_password,
+};
+use analytics::load_usage_analytics;
+use backup::{export_backup, preview_backup, restore_backup};
+use devices::{apply_device_aliases, load_bound_devices, set_mac_custom_name, unbind_devices};
+use kick::{run_kick_policy, set_kick_policy};
+use onedrive::{logout_onedrive, onedrive_logged_in, open_microsoft_login};
+use report::{generate_report, open_reports_dir, set_report_schedule};
+use sync::{set_auto_sync, set_sync_backend, sync_setting};
 #[cfg(target_os = "macos")]
 use tauri::TitleBarStyle;
 use tauri::{WebviewUrl, WebviewWindow, WebviewWindowBuilder};
@@ -18,6 +39,7 @@ pub fn run() {
     let mut builder = tauri::Builder::new()
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_shell::init());
@@ -29,16 +51,36 @@ pub fn run() {
         .manage(AppState::default())
         .invoke_handler(tauri::generate_handler![
             get_cookie,
+            login_via_webvpn_portal,
+            restore_webvpn_session,
             refresh_user_dashboard,
+            load_accounts_summary,
+            switch_session,
+            set_account_preferences,
+            update_account_password,
+            remove_account,
+            forget_all_credentials,
             load_online_list,
             load_login_history,
             do_to_offline,
             load_month_pay,
             load_user_online_log,
-            load_mac_address,
+            load_usage_analytics,
             get_current_device_mac,
-            do_unbind_mac,
+            list_network_interfaces,
+            set_login_interface,
+            load_bound_devices,
+            unbind_devices,
+            apply_device_aliases,
+            set_kick_policy,
+            run_kick_policy,
+            generate_report,
+            set_report_schedule,
+            open_reports_dir,
             open_speed_test,
+            open_webvpn_browser,
+            start_webvpn_proxy,
+            stop_webvpn_proxy,
             load_ip_address,
             get_stored_cookie_str,
             load_setting,
@@ -55,6 +97,14 @@ pub fn run() {
             set_mac_custom_name,
             collapse,
             open_microsoft_login,
+            onedrive_logged_in,
+            logout_onedrive,
+            sync_setting,
+            set_sync_backend,
+            set_auto_sync,
+            export_backup,
+            preview_backup,
+            restore_backup,
             get_ip_location,
             // switch_login_ustb_wifi,
             get_current_user_name,
@@ -62,6 +112,9 @@ pub fn run() {
             load_electric_bill,
             translate_up,
             translate_down,
+            save_webvpn_profile,
+            remove_webvpn_profile,
+            select_webvpn_profile,
         ])
         .setup(|app| {
             // {
@@ -85,6 +138,10 @@ pub fn run() {
             {
                 background_init(&window)?;
             }
+            tauri::async_runtime::spawn(sync::sync_on_startup(app.handle().clone()));
+            tauri::async_runtime::spawn(kick::kick_task(app.handle().clone()));
+            tauri::async_runtime::spawn(report::report_task(app.handle().clone()));
+            tauri::async_runtime::spawn(events::poll_task(app.handle().clone()));
             Ok(())
         })
         .run(tauri::generate_context!())
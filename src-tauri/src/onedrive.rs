@@ -1,55 +1,177 @@
-use crate::{entities::AppState, requests::CLIENT, setting::Setting};
-use base64::{engine::general_purpose::URL_SAFE, Engine as _};
-use serde::Deserialize;
+use std::{fs, time::Duration};
+
+use crate::{
+    entities::AppState,
+    requests::CLIENT,
+    sync::{RemoteMeta, SyncBackend, SyncBackendConfig, SyncError},
+    utils::{get_onedrive_token_path, write_private_file},
+};
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE};
+use chrono::{DateTime, Local, Utc};
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use tauri::{Manager, WebviewWindow};
-use tauri_plugin_dialog::DialogExt;
+use tauri::{Manager, Url, WebviewWindow};
 
-#[tauri::command(async)]
-pub async fn open_microsoft_login(app_handle: tauri::AppHandle) -> Result<(), String> {
-    let url: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize?client_id=6c2e411f-bea9-4598-8cb9-bebadac59bdc&scope=Files.ReadWrite%20offline_access&response_type=code";
+const CLIENT_ID: &str = "6c2e411f-bea9-4598-8cb9-bebadac59bdc";
+const AUTHORIZE_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize";
+const REDIRECT_URI: &str = "https://login.microsoftonline.com/common/oauth2/nativeclient";
+const TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+const SCOPE: &str = "Files.ReadWrite offline_access";
+const APP_ROOT_URL: &str = "https://graph.microsoft.com/v1.0/drive/special/approot";
+// refresh_token 存在系统的凭据管理里（钥匙串、Windows 凭据管理器、Secret Service）
+const KEYRING_SERVICE: &str = "ustb-wifi-tools";
+const KEYRING_USER: &str = "onedrive_refresh_token";
 
+// 打开微软登录窗口，登录完成后立即同步一次，返回同步结果
+#[tauri::command(async)]
+pub async fn open_microsoft_login(app_handle: tauri::AppHandle) -> Result<String, SyncError> {
+    if app_handle.get_webview_window("Onedrive").is_some() {
+        return Err(SyncError::Other("已经打开一个登录窗口了".to_string()));
+    }
     let code_verifier = generate_random_string(128);
     let code_challenge = sha256_base64url(&code_verifier);
-    *app_handle
-        .state::<AppState>()
-        .onedrive_code_verifier
-        .write()
-        .await = Some(code_verifier);
-
-    let url = format!(
-        "{}&code_challenge={}&code_challenge_method=S256&redirect_uri=https%3A%2F%2Flogin.microsoftonline.com%2Fcommon%2Foauth2%2Fnativeclient",
-        url, code_challenge
-    );
-    let app_handle = app_handle.clone();
-    let mut win = WebviewWindow::builder(
-        &app_handle,
-        "Onedrive",
-        tauri::WebviewUrl::External(url.parse().unwrap()),
-    );
+    let mut url = Url::parse(AUTHORIZE_URL).map_err(anyhow::Error::from)?;
+    url.query_pairs_mut()
+        .append_pair("client_id", CLIENT_ID)
+        .append_pair("scope", SCOPE)
+        .append_pair("response_type", "code")
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("redirect_uri", REDIRECT_URI);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let tx = std::sync::Mutex::new(Some(tx));
+    let mut win = WebviewWindow::builder(&app_handle, "Onedrive", tauri::WebviewUrl::External(url))
+        .on_navigation(move |url| {
+            if url.path() != "/common/oauth2/nativeclient" {
+                return true;
+            }
+            if let Some(tx) = tx.lock().ok().and_then(|mut tx| tx.take()) {
+                let _ = tx.send(parse_redirect(url));
+            }
+            // 回调页面是空白的，不用加载
+            false
+        });
 
     #[cfg(not(target_os = "android"))]
     {
         win = win.inner_size(480.0, 670.0).title("Onedrive 登录");
     }
+    let _window = win.build().map_err(anyhow::Error::from)?;
 
-    let app_handle = app_handle.clone();
-    win.on_navigation(move |url| {
-        dbg!(url.path());
-        if url.path() == "/common/oauth2/nativeclient" {
-            let pair = url.query_pairs().next().unwrap();
-            let code = pair.1.to_string();
-            // dbg!(code);
-            let app_handle = app_handle.clone();
-            tauri::async_runtime::spawn(async move {
-                let _ = code_to_access_token(app_handle, code).await;
-            });
+    let res = tokio::time::timeout(Duration::from_secs(300), rx).await;
+    #[cfg(not(target_os = "android"))]
+    {
+        let _ = _window.close();
+    }
+    // 窗口被关掉时 tx 会被丢弃
+    let code = res
+        .map_err(|_| SyncError::Other("登录超时".to_string()))?
+        .map_err(|_| SyncError::Cancelled)??;
+
+    let token = request_token(
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", &code_verifier),
+        ],
+        None,
+    )
+    .await?;
+    token.save(&app_handle)?;
+
+    // 登录了 OneDrive 就用它同步
+    let state = app_handle.state::<AppState>();
+    state.setting.write().await.sync_backend = Some(SyncBackendConfig::OneDrive);
+    state.setting.read().await.write_setting(&app_handle)?;
+    let report = crate::sync::sync(&app_handle, false).await?;
+    serde_json::to_string(&report).map_err(|e| SyncError::Other(e.to_string()))
+}
+
+// 回调地址上带着授权码，或者 error=access_denied（用户点了取消）
+fn parse_redirect(url: &Url) -> Result<String, SyncError> {
+    let (mut code, mut error, mut description) = (None, None, String::new());
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "error" => error = Some(value.into_owned()),
+            "error_description" => description = value.into_owned(),
+            _ => {}
         }
-        true
-    })
-    .build()
-    .map_err(|e| e.to_string())?;
-    Ok(())
+    }
+    match (code, error) {
+        (_, Some(error)) if error == "access_denied" => Err(SyncError::Cancelled),
+        (_, Some(error)) => Err(SyncError::Remote {
+            status: None,
+            code: error,
+            message: description,
+        }),
+        (Some(code), None) => Ok(code),
+        (None, None) => Err(SyncError::Other("登录回调中没有授权码".to_string())),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphErrorBody {
+    error: ErrorDetail,
+}
+
+// Graph 的错误格式：{"error": {"code": "...", "message": "..."}}
+fn graph_error(status: StatusCode, body: &str) -> SyncError {
+    match status {
+        StatusCode::UNAUTHORIZED => SyncError::Auth("OneDrive 登录已失效，请重新登录".to_string()),
+        StatusCode::INSUFFICIENT_STORAGE => SyncError::QuotaExceeded,
+        _ => {
+            let detail = serde_json::from_str::<GraphErrorBody>(body)
+                .map(|body| body.error)
+                .unwrap_or_default();
+            SyncError::Remote {
+                status: Some(status.as_u16()),
+                code: detail.code,
+                message: detail.message,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TokenErrorBody {
+    error: String,
+    error_description: String,
+}
+
+// 登录接口的错误格式：{"error": "invalid_grant", "error_description": "..."}
+fn token_error(status: StatusCode, body: &str) -> SyncError {
+    let body = serde_json::from_str::<TokenErrorBody>(body).unwrap_or_default();
+    match body.error.as_str() {
+        // refresh_token 过期或者被撤销
+        "invalid_grant" => SyncError::Auth("OneDrive 登录已失效，请重新登录".to_string()),
+        _ => SyncError::Remote {
+            status: Some(status.as_u16()),
+            code: body.error,
+            message: body.error_description,
+        },
+    }
+}
+
+async fn check_graph(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(graph_error(status, &body).into())
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,157 +185,206 @@ struct TokenResponse {
     refresh_token: Option<String>,
 }
 
-async fn code_to_access_token(app_handle: tauri::AppHandle, code: String) -> Result<(), String> {
-    dbg!(&code);
-    let window = app_handle
-        .get_webview_window("Onedrive")
-        .ok_or("?".to_string())?;
+// OneDrive 登录凭据，单独存一个只有当前用户可读的文件，不参与同步
+// 能用系统凭据管理时 refresh_token 不写进文件，文件里这一项为空
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OneDriveToken {
+    access_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    refresh_token: String,
+    expires_at: i64,
+}
 
-    #[cfg(not(target_os = "android"))]
-    {
-        let _ = window.hide();
-    }
-
-    let code_verifier = app_handle
-        .state::<AppState>()
-        .onedrive_code_verifier
-        .read()
-        .await
-        .clone()
-        .ok_or("?".to_string())?;
-
-    let response = match CLIENT
-        .post("https://login.microsoftonline.com/common/oauth2/v2.0/token")
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        // .header("Origin", "https://login.microsoftonline.com/common/oauth2/nativeclient")
-        .form(&[
-            ("client_id", "6c2e411f-bea9-4598-8cb9-bebadac59bdc"),
-            (
-                "redirect_uri",
-                "https://login.microsoftonline.com/common/oauth2/nativeclient",
-            ),
-            ("code", &code),
-            ("grant_type", "authorization_code"),
-            ("code_verifier", &code_verifier),
-        ])
-        .send()
-        .await
-    {
-        Ok(res) => res,
+fn keyring_entry() -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?)
+}
+
+impl OneDriveToken {
+    fn from_response(res: TokenResponse, old_refresh_token: Option<String>) -> Result<Self> {
+        Ok(OneDriveToken {
+            access_token: res.access_token.ok_or(anyhow!("没有拿到 access_token"))?,
+            // 刷新时微软不一定返回新的 refresh_token
+            refresh_token: res
+                .refresh_token
+                .or(old_refresh_token)
+                .ok_or(anyhow!("没有拿到 refresh_token"))?,
+            expires_at: Local::now().timestamp() + res.expires_in.unwrap_or(3600) as i64,
+        })
+    }
+
+    // 提前一分钟当作过期
+    fn is_expired(&self) -> bool {
+        Local::now().timestamp() + 60 >= self.expires_at
+    }
+
+    fn load(app: &tauri::AppHandle) -> Result<Option<Self>> {
+        let mut token: Self = match fs::read(get_onedrive_token_path(app)?) {
+            Ok(buf) => match serde_json::from_slice(&buf) {
+                Ok(token) => token,
+                Err(_) => return Ok(None),
+            },
+            // 没有该文件
+            Err(_) => return Ok(None),
+        };
+        if token.refresh_token.is_empty() {
+            // 凭据管理里找不到就当作没登录，让用户重新登录
+            match keyring_entry().and_then(|entry| Ok(entry.get_password()?)) {
+                Ok(refresh_token) => token.refresh_token = refresh_token,
+                Err(_) => return Ok(None),
+            }
+        }
+        Ok(Some(token))
+    }
+
+    fn save(&self, app: &tauri::AppHandle) -> Result<()> {
+        let mut token = self.clone();
+        // 没有可用的凭据管理（比如 Linux 上没装 Secret Service）时才退回写文件
+        if keyring_entry()
+            .and_then(|entry| Ok(entry.set_password(&self.refresh_token)?))
+            .is_ok()
+        {
+            token.refresh_token.clear();
+        }
+        write_private_file(&get_onedrive_token_path(app)?, &serde_json::to_vec(&token)?)
+    }
+
+    fn clear(app: &tauri::AppHandle) -> Result<()> {
+        let path = get_onedrive_token_path(app)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        // 凭据管理不可用时 refresh_token 本来就在文件里，已经删掉了
+        if let Ok(entry) = keyring_entry() {
+            let _ = entry.delete_credential();
+        }
+        Ok(())
+    }
+}
+
+async fn request_token(
+    params: &[(&str, &str)],
+    old_refresh_token: Option<String>,
+) -> Result<OneDriveToken> {
+    let mut form = vec![("client_id", CLIENT_ID), ("scope", SCOPE)];
+    form.extend_from_slice(params);
+    let response = CLIENT.post(TOKEN_URL).form(&form).send().await?;
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        return Err(token_error(status, &text).into());
+    }
+    let res: TokenResponse = serde_json::from_str(&text)
+        .map_err(|_| SyncError::InvalidData("无法解析微软返回的登录凭据".to_string()))?;
+    OneDriveToken::from_response(res, old_refresh_token)
+}
+
+// 用 refresh_token 换新的 access_token，没过期就直接用
+async fn access_token(app: &tauri::AppHandle) -> Result<OneDriveToken> {
+    let token =
+        OneDriveToken::load(app)?.ok_or(SyncError::Auth("还没有登录 OneDrive".to_string()))?;
+    if !token.is_expired() {
+        return Ok(token);
+    }
+    let res = request_token(
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &token.refresh_token),
+        ],
+        Some(token.refresh_token.clone()),
+    )
+    .await;
+    let new_token = match res {
+        Ok(new_token) => new_token,
         Err(e) => {
-            dbg!(&e);
-            app_handle
-                .dialog()
-                .message("由于网络问题失败。")
-                .blocking_show();
-            #[cfg(not(target_os = "android"))]
-            {
-                let _ = window.close();
+            // 只能重新登录了，删掉没用的凭据
+            if matches!(e.downcast_ref::<SyncError>(), Some(SyncError::Auth(_))) {
+                OneDriveToken::clear(app)?;
             }
-            return Err(e.to_string());
+            return Err(e);
         }
     };
+    new_token.save(app)?;
+    Ok(new_token)
+}
+
+// 文件放在 OneDrive 的应用文件夹里
+pub struct OneDriveBackend {
+    access_token: String,
+}
+
+impl OneDriveBackend {
+    pub async fn new(app: &tauri::AppHandle) -> Result<Self> {
+        Ok(OneDriveBackend {
+            access_token: access_token(app).await?.access_token,
+        })
+    }
+}
 
-    let text = response.text().await.unwrap();
-    // dbg!(&text);
-    let token_response: TokenResponse = serde_json::from_str(&text).unwrap();
+fn parse_drive_item(item: &serde_json::Value) -> Result<RemoteMeta> {
+    let modified = item["lastModifiedDateTime"]
+        .as_str()
+        .ok_or(anyhow!("OneDrive 没有返回修改时间"))?;
+    Ok(RemoteMeta {
+        version: modified.to_string(),
+        modified: DateTime::parse_from_rfc3339(modified)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+    })
+}
 
-    let ans = app_handle
-        .dialog()
-        .message("在 OneDrive 上传配置文件还是下载？")
-        .title("同步选项")
-        .buttons(tauri_plugin_dialog::MessageDialogButtons::OkCancelCustom(
-            "上传".to_string(),
-            "下载".to_string(),
-        ))
-        .blocking_show();
+impl SyncBackend for OneDriveBackend {
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>> {
+        let response = CLIENT
+            .get(format!("{}:/{}", APP_ROOT_URL, name))
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let item: serde_json::Value = check_graph(response).await?.json().await?;
+        Ok(Some(parse_drive_item(&item)?))
+    }
 
-    match ans {
-        true => upload_setting_to_onedrive(&app_handle, token_response).await,
-        false => download_setting_to_onedrive(&app_handle, token_response).await,
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let response = CLIENT
+            .get(format!("{}:/{}:/content", APP_ROOT_URL, name))
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+        Ok(check_graph(response).await?.bytes().await?.to_vec())
     }
 
-    #[cfg(not(target_os = "android"))]
-    {
-        let _ = window.close();
-    }
-    Ok(())
-}
-
-async fn upload_setting_to_onedrive(app_handle: &tauri::AppHandle, token_response: TokenResponse) {
-    let state = app_handle
-        .state::<AppState>()
-        .setting
-        .read()
-        .await
-        .clone();
-    let s = serde_json::json!(&state).to_string();
-    let s = URL_SAFE.encode(&s);
-    let response = CLIENT
-        .put("https://graph.microsoft.com/v1.0/drive/special/approot:/setting.txt:/content")
-        .bearer_auth(token_response.access_token.unwrap())
-        .header("Content-Type", "text/plain")
-        .body(s)
-        .send()
-        .await;
-    if let Err(e) = response {
-        app_handle
-            .dialog()
-            .message("上传失败！可能由于网络问题")
-            .blocking_show();
-        println!("Error uploading setting: {}", e);
-    } else {
-        app_handle.dialog().message("上传成功！").blocking_show();
-        // dbg!(response.unwrap().text().await);
-    }
-}
-
-async fn download_setting_to_onedrive(
-    app_handle: &tauri::AppHandle,
-    token_response: TokenResponse,
-) {
-    let response = CLIENT
-        .get("https://graph.microsoft.com/v1.0/drive/special/approot:/setting.txt:/content")
-        .bearer_auth(token_response.access_token.unwrap())
-        .send()
-        .await;
-    if let Err(e) = response {
-        app_handle
-            .dialog()
-            .message("下载失败！可能由于网络问题")
-            .blocking_show();
-        println!("Error downloading setting: {}", e);
-    } else {
-        let text = response.unwrap().text().await.unwrap();
-        let text = match URL_SAFE.decode(text.as_bytes()) {
-            Ok(text) => text,
-            Err(e) => {
-                app_handle
-                    .dialog()
-                    .message(format!("解码base64错误：{e:?}"))
-                    .blocking_show();
-                return;
-            }
-        };
-        let setting: Setting = match serde_json::from_slice(&text) {
-            Ok(s) => s,
-            Err(e) => {
-                app_handle
-                    .dialog()
-                    .message(format!("配置文件格式不正确！{e:?}"))
-                    .blocking_show();
-                return;
-            }
-        };
-        // dbg!(&setting);
-        let state = app_handle.state::<AppState>();
-        *state.setting.write().await = setting;
-        let _ = state.setting.write().await.write_setting(app_handle);
-        app_handle.dialog().message("下载成功！").blocking_show();
-        // dbg!(response.unwrap().text().await);
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteMeta> {
+        let response = CLIENT
+            .put(format!("{}:/{}:/content", APP_ROOT_URL, name))
+            .bearer_auth(&self.access_token)
+            .header("Content-Type", "text/plain")
+            .body(data)
+            .send()
+            .await?;
+        let item: serde_json::Value = check_graph(response).await?.json().await?;
+        parse_drive_item(&item)
     }
 }
 
+#[tauri::command(async)]
+pub async fn onedrive_logged_in(app: tauri::AppHandle) -> Result<bool, String> {
+    Ok(OneDriveToken::load(&app)
+        .map_err(|e| e.to_string())?
+        .is_some())
+}
+
+// 退出登录和清除所有凭据时用
+pub fn clear_token(app: &tauri::AppHandle) -> Result<()> {
+    OneDriveToken::clear(app)
+}
+
+#[tauri::command(async)]
+pub async fn logout_onedrive(app: tauri::AppHandle) -> Result<(), String> {
+    clear_token(&app).map_err(|e| e.to_string())
+}
+
 fn generate_random_string(length: usize) -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
     let mut rng = rand::rng();
@@ -228,3 +399,53 @@ fn sha256_base64url(input: &str) -> String {
     let hash = hasher.finalize();
     URL_SAFE.encode(hash).trim_end_matches('=').to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_redirect() {
+        let url = |s: &str| Url::parse(&format!("{}{}", REDIRECT_URI, s)).unwrap();
+        assert_eq!(
+            parse_redirect(&url("?code=M.C507_abc")).unwrap(),
+            "M.C507_abc"
+        );
+        assert!(matches!(
+            parse_redirect(&url(
+                "?error=access_denied&error_description=The+user+has+denied+access"
+            )),
+            Err(SyncError::Cancelled)
+        ));
+        assert!(matches!(
+            parse_redirect(&url("?error=invalid_request&error_description=bad")),
+            Err(SyncError::Remote { code, .. }) if code == "invalid_request"
+        ));
+        assert!(parse_redirect(&url("")).is_err());
+    }
+
+    #[test]
+    fn test_graph_error() {
+        let body =
+            r#"{"error":{"code":"itemNotFound","message":"The resource could not be found."}}"#;
+        assert!(matches!(
+            graph_error(StatusCode::NOT_FOUND, body),
+            SyncError::Remote { status: Some(404), code, .. } if code == "itemNotFound"
+        ));
+        assert!(matches!(
+            graph_error(StatusCode::UNAUTHORIZED, body),
+            SyncError::Auth(_)
+        ));
+        assert!(matches!(
+            graph_error(StatusCode::INSUFFICIENT_STORAGE, ""),
+            SyncError::QuotaExceeded
+        ));
+        assert!(matches!(
+            token_error(
+                StatusCode::BAD_REQUEST,
+                r#"{"error":"invalid_grant","error_description":"AADSTS70000"}"#
+            ),
+            SyncError::Auth(_)
+        ));
+    }
+}
@@ -0,0 +1,383 @@
+use std::{net::SocketAddr, sync::LazyLock, time::Duration};
+
+use anyhow::{Result, anyhow};
+use reqwest::{
+    Client, Method, Response, StatusCode,
+    header::{COOKIE, HeaderMap, HeaderName, HeaderValue, LOCATION},
+    redirect,
+};
+use tauri::{Manager, Url};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::{
+    entities::{AppState, UserType},
+    utils::webvpn::WebVpnProfile,
+};
+
+pub const DEFAULT_PROXY_PORT: u16 = 7899;
+const MAX_HEAD_LEN: usize = 64 * 1024;
+// 本机的任何程序都能连上来，请求体要限制大小，不能按 Content-Length 随便分配内存
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+// 读取请求头或者请求体超过这个时间就断开
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// accept 出错（比如文件描述符用完了）时等一会儿再接受新连接，不要空转
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// 不能自动重定向，要把 Location 转换回原始地址交给客户端处理
+static PROXY_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .no_proxy()
+        .redirect(redirect::Policy::none())
+        .build()
+        .unwrap_or_default()
+});
+
+// 逐跳首部，不转发
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h))
+}
+
+#[derive(Debug)]
+struct RequestHead {
+    method: String,
+    url: Url,
+    headers: Vec<(String, String)>,
+    content_length: usize,
+}
+
+pub async fn bind(port: u16) -> Result<TcpListener> {
+    Ok(TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port))).await?)
+}
+
+// 每个连接只处理一个请求，处理完就关闭
+pub async fn serve(listener: TcpListener, app: tauri::AppHandle) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+            continue;
+        };
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            // 出错时连接多半已经断了，没有地方可以报告
+            let _ = handle_connection(stream, &app).await;
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, app: &tauri::AppHandle) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let head = match timeout(READ_TIMEOUT, read_head(&mut reader)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(e)) => {
+            return write_error(reader.get_mut(), 400, "Bad Request", &e.to_string()).await;
+        }
+        Err(_) => {
+            return write_error(reader.get_mut(), 408, "Request Timeout", "读取请求头超时").await;
+        }
+    };
+    if head.content_length > MAX_BODY_LEN {
+        return write_error(
+            reader.get_mut(),
+            413,
+            "Payload Too Large",
+            &format!("请求体不能超过 {} MB", MAX_BODY_LEN / 1024 / 1024),
+        )
+        .await;
+    }
+    if head.method.eq_ignore_ascii_case("CONNECT") {
+        return write_error(
+            reader.get_mut(),
+            405,
+            "Method Not Allowed",
+            "不支持 HTTPS 隧道，请直接用 http:// 或 https:// 的完整地址请求",
+        )
+        .await;
+    }
+    let app_state = app.state::<AppState>();
+    let (profile, cookie_str) = match (
+        app_state.user_type.read().await.clone(),
+        app_state.cookie_str.read().await.clone(),
+    ) {
+        (UserType::ViaVpn(profile), Some(cookie_str)) => (profile, cookie_str),
+        _ => {
+            return write_error(reader.get_mut(), 502, "Bad Gateway", "WebVPN 未登录").await;
+        }
+    };
+    if !profile.is_campus_url(&head.url) {
+        return write_error(reader.get_mut(), 403, "Forbidden", "只代理校内地址").await;
+    }
+
+    let mut body = vec![0; head.content_length];
+    match timeout(READ_TIMEOUT, reader.read_exact(&mut body)).await {
+        Ok(res) => {
+            res?;
+        }
+        Err(_) => {
+            return write_error(reader.get_mut(), 408, "Request Timeout", "读取请求体超时").await;
+        }
+    }
+
+    match forward(&head, &profile, &cookie_str, body).await {
+        Ok((response_head, mut response)) => {
+            let stream = reader.get_mut();
+            stream.write_all(&response_head).await?;
+            // 响应体边收边转发，不整个读进内存
+            while let Some(chunk) = response.chunk().await? {
+                stream.write_all(&chunk).await?;
+            }
+        }
+        Err(e) => write_error(reader.get_mut(), 502, "Bad Gateway", &e.to_string()).await?,
+    }
+    Ok(())
+}
+
+async fn read_head<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Result<RequestHead> {
+    let mut lines = vec![];
+    let mut total = 0;
+    loop {
+        let mut line = String::new();
+        let n = reader.read_line(&mut line).await?;
+        total += n;
+        if n == 0 || total > MAX_HEAD_LEN {
+            return Err(anyhow!("请求头不完整"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    parse_head(&lines)
+}
+
+fn parse_head(lines: &[String]) -> Result<RequestHead> {
+    let mut request_line = lines
+        .first()
+        .ok_or(anyhow!("没有请求行"))?
+        .split_whitespace();
+    let method = request_line.next().ok_or(anyhow!("没有请求方法"))?;
+    let target = request_line.next().ok_or(anyhow!("没有请求地址"))?;
+
+    let mut headers = vec![];
+    let mut host = None;
+    let mut content_length = 0;
+    for line in &lines[1..] {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(anyhow!("请求头格式错误：{}", line))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse()?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(anyhow!("不支持分块传输的请求体"));
+        }
+        headers.push((name.to_string(), value.to_string()));
+    }
+
+    // 代理请求是完整地址，直接请求时是路径 + Host
+    let url = if target.starts_with('/') {
+        let host = host.ok_or(anyhow!("没有 Host"))?;
+        Url::parse(&format!("http://{}{}", host, target))?
+    } else {
+        Url::parse(target)?
+    };
+
+    Ok(RequestHead {
+        method: method.to_string(),
+        url,
+        headers,
+        content_length,
+    })
+}
+
+async fn forward(
+    head: &RequestHead,
+    profile: &WebVpnProfile,
+    cookie_str: &str,
+    body: Vec<u8>,
+) -> Result<(Vec<u8>, Response)> {
+    let vpn_url = Url::parse(&profile.translate_up(head.url.as_str())?)?;
+
+    let mut headers = HeaderMap::new();
+    let mut cookies = vec![cookie_str.to_string()];
+    for (name, value) in &head.headers {
+        if is_hop_by_hop(name) {
+            continue;
+        }
+        if name.eq_ignore_ascii_case("cookie") {
+            cookies.push(value.clone());
+            continue;
+        }
+        headers.append(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    headers.insert(COOKIE, HeaderValue::from_str(&cookies.join("; "))?);
+
+    let response = PROXY_CLIENT
+        .request(Method::from_bytes(head.method.as_bytes())?, vpn_url.clone())
+        .headers(headers)
+        .body(body)
+        .send()
+        .await?;
+
+    let res = response_head(
+        response.status(),
+        response.headers(),
+        response.content_length(),
+        profile,
+        &vpn_url,
+    );
+    Ok((res, response))
+}
+
+// 响应头原样按字节转发，不是 ASCII 的值也不会丢；没有长度时靠关闭连接表示结束
+fn response_head(
+    status: StatusCode,
+    headers: &HeaderMap,
+    content_length: Option<u64>,
+    profile: &WebVpnProfile,
+    vpn_url: &Url,
+) -> Vec<u8> {
+    let mut res = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+    .into_bytes();
+    for (name, value) in headers {
+        if is_hop_by_hop(name.as_str()) {
+            continue;
+        }
+        res.extend_from_slice(name.as_str().as_bytes());
+        res.extend_from_slice(b": ");
+        match value.to_str() {
+            Ok(location) if name == LOCATION => {
+                res.extend_from_slice(translate_location(profile, vpn_url, location).as_bytes())
+            }
+            _ => res.extend_from_slice(value.as_bytes()),
+        }
+        res.extend_from_slice(b"\r\n");
+    }
+    if let Some(len) = content_length {
+        res.extend_from_slice(format!("content-length: {len}\r\n").as_bytes());
+    }
+    res.extend_from_slice(b"connection: close\r\n\r\n");
+    res
+}
+
+// 把 WebVPN 返回的跳转地址转换回原始地址，转换不了就原样返回
+fn translate_location(profile: &WebVpnProfile, base: &Url, location: &str) -> String {
+    match base.join(location) {
+        Ok(url) if url.host_str() == Some(profile.domain().as_str()) => profile
+            .translate_down(url.as_str())
+            .unwrap_or(location.to_string()),
+        _ => location.to_string(),
+    }
+}
+
+async fn write_error(stream: &mut TcpStream, code: u16, reason: &str, msg: &str) -> Result<()> {
+    let res = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: text/plain; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        code,
+        reason,
+        msg.len(),
+        msg
+    );
+    stream.write_all(res.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let head = parse_head(&lines(
+            "GET http://speed.ustb.edu.cn/a?b=c HTTP/1.1\nHost: speed.ustb.edu.cn\nContent-Length: 3",
+        ))
+        .unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.url.as_str(), "http://speed.ustb.edu.cn/a?b=c");
+        assert_eq!(head.content_length, 3);
+
+        let head = parse_head(&lines("POST /login HTTP/1.1\nHost: 202.204.48.66:801")).unwrap();
+        assert_eq!(head.url.as_str(), "http://202.204.48.66:801/login");
+
+        assert!(parse_head(&lines("GET /login HTTP/1.1")).is_err());
+        assert!(
+            parse_head(&lines(
+                "POST http://speed.ustb.edu.cn/ HTTP/1.1\nTransfer-Encoding: chunked"
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_response_head() {
+        let profile = WebVpnProfile::default();
+        let base = Url::parse(&profile.translate_up("http://202.204.48.66/").unwrap()).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "content-disposition",
+            HeaderValue::from_bytes("attachment; filename=\"报告.pdf\"".as_bytes()).unwrap(),
+        );
+        headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        headers.insert(
+            LOCATION,
+            HeaderValue::from_str(&profile.translate_up("http://202.204.48.66/login").unwrap())
+                .unwrap(),
+        );
+        let head = response_head(StatusCode::FOUND, &headers, Some(5), &profile, &base);
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(head.contains("content-disposition: attachment; filename=\"报告.pdf\"\r\n"));
+        assert!(head.contains("location: http://202.204.48.66/login\r\n"));
+        assert!(!head.contains("transfer-encoding"));
+        assert!(head.ends_with("content-length: 5\r\nconnection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_translate_location() {
+        let profile = WebVpnProfile::default();
+        let base = Url::parse(&profile.translate_up("http://202.204.48.66/").unwrap()).unwrap();
+        assert_eq!(
+            translate_location(
+                &profile,
+                &base,
+                "/https/77726476706e69737468656265737421e3e7409f227e6a5972018ba5945c6d36db05/13161874"
+            ),
+            "https://space.bilibili.com/13161874"
+        );
+        assert_eq!(
+            translate_location(&profile, &base, "https://www.ustb.edu.cn/"),
+            "https://www.ustb.edu.cn/"
+        );
+    }
+}
@@ -0,0 +1,697 @@
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use anyhow::{Result, anyhow};
+use chrono::{Datelike, Days, FixedOffset, Local, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_opener::OpenerExt;
+
+use crate::{
+    accounts::{ensure_session, user_type_for},
+    analytics::{DeviceUsage, device_breakdown, hour_of_week_heatmap},
+    entities::{AppState, UserSession},
+    events::{self, AppEvent},
+    requests::get_month_pay,
+    setting::{
+        device_alias::DeviceAlias,
+        report_schedule::{ReportFormat, ReportSchedule},
+    },
+    usage::{MonthBill, OnlineLogRow, fetch_online_log, parse_month_pay, parse_online_log},
+    utils::{campus_offset, campus_today, get_store_path},
+};
+
+const TOP_DEVICES: usize = 5;
+const PEAK_HOURS: usize = 3;
+// 启动后等一会儿再检查，不和启动时的同步、登录抢
+const STARTUP_DELAY: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+// 自动生成失败后隔 1、2、4、8 小时重试，还失败就放弃这个周期，等下个周期
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportKind {
+    Weekly,
+    Monthly,
+}
+
+// 报告覆盖的日期，两头都包含
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportPeriod {
+    pub kind: ReportKind,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl ReportPeriod {
+    // today 之前最近一个完整的周（周一到周日）或者月
+    pub fn last_complete(kind: ReportKind, today: NaiveDate) -> Self {
+        let (start, end) = match kind {
+            ReportKind::Weekly => {
+                let monday = today - Days::new(today.weekday().num_days_from_monday() as u64);
+                (monday - Days::new(7), monday - Days::new(1))
+            }
+            ReportKind::Monthly => {
+                let first_day = today - Days::new(today.day0() as u64);
+                (first_day - Months::new(1), first_day - Days::new(1))
+            }
+        };
+        ReportPeriod { kind, start, end }
+    }
+
+    fn title(&self) -> String {
+        match self.kind {
+            ReportKind::Weekly => format!("{} ~ {} 周报", self.start, self.end),
+            ReportKind::Monthly => format!("{} 月报", self.start.format("%Y-%m")),
+        }
+    }
+
+    fn file_name(&self, user_name: &str, format: ReportFormat) -> String {
+        let kind = match self.kind {
+            ReportKind::Weekly => "weekly",
+            ReportKind::Monthly => "monthly",
+        };
+        format!("{user_name}_{kind}_{}.{}", self.start, format.extension())
+    }
+}
+
+// 一个账号一段时间的使用情况，流量单位 MB，时长单位分钟
+#[derive(Debug, Clone)]
+pub struct UsageReport {
+    pub user_name: String,
+    pub period: ReportPeriod,
+    pub flow: f64,
+    pub ipv4_flow: f64,
+    pub ipv6_flow: f64,
+    pub minutes: f64,
+    pub cost: f64,
+    pub sessions: usize,
+    pub bill: Option<MonthBill>, // 月报才有，校园网后台的月度账单
+    pub top_devices: Vec<DeviceUsage>,
+    pub peak_hours: Vec<(u32, f64)>, // 几点钟，这个小时里一共在线的分钟数
+}
+
+// 报告里的一个表格
+struct Section {
+    heading: &'static str,
+    header: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+}
+
+fn format_flow(mb: f64) -> String {
+    if mb < 1024.0 {
+        format!("{mb:.2} MB")
+    } else {
+        format!("{:.2} GB", mb / 1024.0)
+    }
+}
+
+fn format_minutes(minutes: f64) -> String {
+    format!("{:.1} 小时", minutes / 60.0)
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl UsageReport {
+    pub fn build(
+        user_name: &str,
+        period: ReportPeriod,
+        rows: &[OnlineLogRow],
+        bills: &[MonthBill],
+        aliases: &[DeviceAlias],
+        offset: &FixedOffset,
+    ) -> Self {
+        let mut devices = device_breakdown(rows, aliases);
+        devices.truncate(TOP_DEVICES);
+        let mut hours = [0.0; 24];
+        for cell in hour_of_week_heatmap(rows, offset) {
+            hours[cell.hour as usize] += cell.minutes;
+        }
+
+        let mut peak_hours: Vec<(u32, f64)> = (0..24)
+            .map(|h| (h, hours[h as usize]))
+            .filter(|(_, minutes)| *minutes > 0.0)
+            .collect();
+        peak_hours.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peak_hours.truncate(PEAK_HOURS);
+
+        let bill = match period.kind {
+            ReportKind::Weekly => None,
+            ReportKind::Monthly => bills
+                .iter()
+                .find(|b| b.month(offset) == Some((period.start.year(), period.start.month())))
+                .cloned(),
+        };
+
+        UsageReport {
+            user_name: user_name.to_string(),
+            period,
+            flow: rows.iter().map(|r| r.flow).sum(),
+            ipv4_flow: rows.iter().map(OnlineLogRow::ipv4_flow).sum(),
+            ipv6_flow: rows.iter().map(OnlineLogRow::ipv6_flow).sum(),
+            minutes: rows.iter().map(|r| r.time).sum(),
+            cost: rows.iter().map(|r| r.cost_money).sum(),
+            sessions: rows.len(),
+            bill,
+            top_devices: devices,
+            peak_hours,
+        }
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let mut overview = vec![vec!["总流量".to_string(), format_flow(self.flow)]];
+        // 有的记录没有分 IPv4、IPv6
+        if self.ipv4_flow + self.ipv6_flow > 0.0 {
+            overview.push(vec!["IPv4 流量".to_string(), format_flow(self.ipv4_flow)]);
+            overview.push(vec!["IPv6 流量".to_string(), format_flow(self.ipv6_flow)]);
+        }
+        overview.push(vec!["在线时长".to_string(), format_minutes(self.minutes)]);
+        overview.push(vec!["上网次数".to_string(), self.sessions.to_string()]);
+        overview.push(vec!["花费".to_string(), format!("{:.2} 元", self.cost)]);
+        if let Some(bill) = &self.bill {
+            overview.push(vec![
+                "月度账单".to_string(),
+                format!(
+                    "{:.2} 元，{}，{}",
+                    bill.used_money,
+                    format_flow(bill.used_flow),
+                    format_minutes(bill.used_time)
+                ),
+            ]);
+        }
+
+        let devices = self
+            .top_devices
+            .iter()
+            .map(|d| {
+                vec![
+                    d.name.clone().unwrap_or_default(),
+                    d.mac.clone(),
+                    format_flow(d.flow),
+                    format_minutes(d.minutes),
+                    d.sessions.to_string(),
+                ]
+            })
+            .collect();
+        let hours = self
+            .peak_hours
+            .iter()
+            .map(|(hour, minutes)| {
+                vec![
+                    format!("{:02}:00 - {:02}:00", hour, hour + 1),
+                    format_minutes(*minutes),
+                ]
+            })
+            .collect();
+
+        vec![
+            Section {
+                heading: "概览",
+                header: &["项目", "数值"],
+                rows: overview,
+            },
+            Section {
+                heading: "流量最多的设备",
+                header: &["设备名", "MAC", "流量", "在线时长", "上网次数"],
+                rows: devices,
+            },
+            Section {
+                heading: "最常上网的时段",
+                header: &["时段", "在线时长"],
+                rows: hours,
+            },
+        ]
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut res = format!("# {} {}\n", self.user_name, self.period.title());
+        for section in self.sections() {
+            res += &format!("\n## {}\n\n", section.heading);
+            if section.rows.is_empty() {
+                res += "没有记录\n";
+                continue;
+            }
+            res += &format!("| {} |\n", section.header.join(" | "));
+            res += &format!("|{}\n", " --- |".repeat(section.header.len()));
+            for row in section.rows {
+                let row: Vec<_> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+                res += &format!("| {} |\n", row.join(" | "));
+            }
+        }
+        res
+    }
+
+    pub fn to_html(&self) -> String {
+        let title = escape_html(&format!("{} {}", self.user_name, self.period.title()));
+        let mut res = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+             <style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #ccc; padding: 4px 8px; }}</style>\n\
+             </head>\n<body>\n<h1>{title}</h1>\n"
+        );
+        for section in self.sections() {
+            res += &format!("<h2>{}</h2>\n", section.heading);
+            if section.rows.is_empty() {
+                res += "<p>没有记录</p>\n";
+                continue;
+            }
+            res += "<table>\n<tr>";
+            for cell in section.header {
+                res += &format!("<th>{cell}</th>");
+            }
+            res += "</tr>\n";
+            for row in section.rows {
+                res += "<tr>";
+                for cell in row {
+                    res += &format!("<td>{}</td>", escape_html(&cell));
+                }
+                res += "</tr>\n";
+            }
+            res += "</table>\n";
+        }
+        res += "</body>\n</html>\n";
+        res
+    }
+}
+
+// 定时生成报告后通过 ReportGenerated 事件发给前端
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportGenerated {
+    pub user_name: String,
+    pub title: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportFailed {
+    pub user_name: String,
+    pub title: String,
+    pub error: String,
+    pub attempts: u32,
+    pub gave_up: bool, // 这个周期不再自动重试
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failure {
+    attempts: u32,
+    retry_at: i64,
+}
+
+// 自动生成失败的记录，每个账号每个周期一条，只在内存里，重启后会重新尝试
+#[derive(Debug, Default)]
+struct FailureLog(HashMap<(String, ReportKind, NaiveDate), Failure>);
+
+impl FailureLog {
+    fn key(user_name: &str, period: &ReportPeriod) -> (String, ReportKind, NaiveDate) {
+        (user_name.to_string(), period.kind, period.start)
+    }
+
+    fn should_try(&self, user_name: &str, period: &ReportPeriod, now: i64) -> bool {
+        self.0
+            .get(&Self::key(user_name, period))
+            .is_none_or(|f| f.attempts < MAX_ATTEMPTS && now >= f.retry_at)
+    }
+
+    // 返回这个周期已经失败的次数
+    fn record(&mut self, user_name: &str, period: &ReportPeriod, now: i64) -> u32 {
+        let failure = self
+            .0
+            .entry(Self::key(user_name, period))
+            .or_insert(Failure {
+                attempts: 0,
+                retry_at: now,
+            });
+        failure.attempts += 1;
+        failure.retry_at =
+            now + CHECK_INTERVAL.as_secs() as i64 * (1 << (failure.attempts - 1).min(3));
+        failure.attempts
+    }
+
+    fn clear(&mut self, user_name: &str, period: &ReportPeriod) {
+        self.0.remove(&Self::key(user_name, period));
+    }
+
+    // 到了新的周期，旧周期的记录就没用了
+    fn prune(&mut self, today: NaiveDate) {
+        self.0.retain(|(_, kind, start), _| {
+            ReportPeriod::last_complete(*kind, today).start == *start
+        });
+    }
+}
+
+fn reports_dir(app: &tauri::AppHandle) -> Result<PathBuf> {
+    let mut path = get_store_path(app)?;
+    path.push("reports");
+    if !path.exists() {
+        fs::create_dir(&path)?;
+    }
+    Ok(path)
+}
+
+// 保存了密码的账号会话失效时可以重新登录，没保存的只能用已经登录的会话
+async fn session_for(app: &tauri::AppHandle, user_name: &str) -> Result<UserSession> {
+    let app_state = app.state::<AppState>();
+    let (account, profile) = {
+        let setting = app_state.setting.read().await;
+        let account = setting
+            .account
+            .iter()
+            .find(|a| a.user_name == user_name)
+            .cloned();
+        (account, setting.current_webvpn_profile())
+    };
+    let old = app_state.sessions.read().await.get(user_name).cloned();
+    let Some(account) = account else {
+        return old.ok_or(anyhow!("账号 {user_name} 没有登录，也没有保存密码"));
+    };
+    let user_type = user_type_for(&account, false, &profile);
+    let (session, _) =
+        ensure_session(&account.user_name, &account.password, old, &user_type).await?;
+    app_state
+        .sessions
+        .write()
+        .await
+        .insert(user_name.to_string(), session.clone());
+    Ok(session)
+}
+
+pub async fn generate(
+    app: &tauri::AppHandle,
+    user_name: &str,
+    period: ReportPeriod,
+) -> Result<ReportGenerated> {
+    let session = session_for(app, user_name).await?;
+    let json_str = fetch_online_log(
+        &session.cookie_str,
+        period.start,
+        period.end,
+        &session.user_type,
+        None,
+    )
+    .await?;
+    let rows = parse_online_log(&json_str)?.rows;
+    let bills = match period.kind {
+        ReportKind::Weekly => vec![],
+        ReportKind::Monthly => parse_month_pay(
+            &get_month_pay(
+                &session.cookie_str,
+                period.start.year() as u16,
+                &session.user_type,
+            )
+            .await?,
+        )?,
+    };
+
+    let (format, aliases) = {
+        let app_state = app.state::<AppState>();
+        let setting = app_state.setting.read().await;
+        (
+            setting.report_schedule.format,
+            setting.device_aliases.clone(),
+        )
+    };
+    let report = UsageReport::build(user_name, period, &rows, &bills, &aliases, &campus_offset());
+    let path = reports_dir(app)?.join(period.file_name(user_name, format));
+    fs::write(
+        &path,
+        match format {
+            ReportFormat::Markdown => report.to_markdown(),
+            ReportFormat::Html => report.to_html(),
+        },
+    )?;
+    Ok(ReportGenerated {
+        user_name: user_name.to_string(),
+        title: period.title(),
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+async fn scheduled_accounts(app_state: &AppState, schedule: &ReportSchedule) -> Vec<String> {
+    if !schedule.accounts.is_empty() {
+        return schedule.accounts.clone();
+    }
+    app_state
+        .setting
+        .read()
+        .await
+        .account
+        .iter()
+        .filter(|a| !a.password.is_empty())
+        .map(|a| a.user_name.clone())
+        .collect()
+}
+
+// 启动后每小时检查一次，上一周、上个月的报告还没生成就生成，已经有文件的不会重复生成
+// 失败的按 FailureLog 退避重试，每次失败都发 ReportFailed 事件，放弃时弹通知
+pub async fn report_task(app: tauri::AppHandle) {
+    tokio::time::sleep(STARTUP_DELAY).await;
+    let mut failures = FailureLog::default();
+    loop {
+        let app_state = app.state::<AppState>();
+        let schedule = app_state.setting.read().await.report_schedule.clone();
+        let today = campus_today();
+        failures.prune(today);
+        let kinds = [
+            (ReportKind::Weekly, schedule.weekly),
+            (ReportKind::Monthly, schedule.monthly),
+        ];
+        for (kind, _) in kinds.iter().filter(|(_, enabled)| *enabled) {
+            let period = ReportPeriod::last_complete(*kind, today);
+            for user_name in scheduled_accounts(&app_state, &schedule).await {
+                let exists = reports_dir(&app).map(|dir| {
+                    dir.join(period.file_name(&user_name, schedule.format))
+                        .exists()
+                });
+                if matches!(exists, Ok(true))
+                    || !failures.should_try(&user_name, &period, Local::now().timestamp())
+                {
+                    continue;
+                }
+                // 报告文件夹打不开也算生成失败，一样退避重试、通知
+                let res = match exists {
+                    Ok(_) => generate(&app, &user_name, period).await,
+                    Err(e) => Err(anyhow!("无法打开报告文件夹：{e}")),
+                };
+                match res {
+                    Ok(report) => {
+                        failures.clear(&user_name, &period);
+                        if schedule.notify {
+                            let _ = app
+                                .notification()
+                                .builder()
+                                .title("使用报告已生成")
+                                .body(format!("{} {}", report.user_name, report.title))
+                                .show();
+                        }
+                        events::emit(&app, AppEvent::ReportGenerated(report));
+                    }
+                    Err(e) => {
+                        let attempts =
+                            failures.record(&user_name, &period, Local::now().timestamp());
+                        let failed = ReportFailed {
+                            user_name: user_name.clone(),
+                            title: period.title(),
+                            error: e.to_string(),
+                            attempts,
+                            gave_up: attempts >= MAX_ATTEMPTS,
+                        };
+                        if failed.gave_up && schedule.notify {
+                            let _ = app
+                                .notification()
+                                .builder()
+                                .title("使用报告生成失败")
+                                .body(format!(
+                                    "{} {}：{}",
+                                    failed.user_name, failed.title, failed.error
+                                ))
+                                .show();
+                        }
+                        events::emit(&app, AppEvent::ReportFailed(failed));
+                    }
+                }
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[tauri::command(async)]
+pub async fn set_report_schedule(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    schedule: ReportSchedule,
+) -> Result<(), String> {
+    app_state
+        .setting
+        .write()
+        .await
+        .set_report_schedule(schedule)
+        .map_err(|e| e.to_string())?;
+    app_state
+        .setting
+        .read()
+        .await
+        .write_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+// 立即生成上一周或者上个月的报告，user_name 为空时用当前登录的账号
+#[tauri::command(async)]
+pub async fn generate_report(
+    app: tauri::AppHandle,
+    user_name: Option<String>,
+    kind: ReportKind,
+) -> Result<String, String> {
+    let user_name = match user_name {
+        Some(user_name) => user_name,
+        None => app.state::<AppState>().cur_account.read().await.clone(),
+    };
+    if user_name.is_empty() {
+        return Err("是否已经点击登录校园网后台按钮？".to_string());
+    }
+    let period = ReportPeriod::last_complete(kind, campus_today());
+    let report = generate(&app, &user_name, period)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(serde_json::to_string(&report).unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn open_reports_dir(app: tauri::AppHandle) -> Result<(), String> {
+    let dir = reports_dir(&app).map_err(|e| e.to_string())?;
+    app.opener()
+        .open_path(dir.to_string_lossy(), None::<&str>)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_complete() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        // 2026-03-02 是周一
+        let week = ReportPeriod::last_complete(ReportKind::Weekly, date("2026-03-02"));
+        assert_eq!(
+            (week.start, week.end),
+            (date("2026-02-23"), date("2026-03-01"))
+        );
+        let week = ReportPeriod::last_complete(ReportKind::Weekly, date("2026-03-08"));
+        assert_eq!(
+            (week.start, week.end),
+            (date("2026-02-23"), date("2026-03-01"))
+        );
+        let month = ReportPeriod::last_complete(ReportKind::Monthly, date("2026-03-31"));
+        assert_eq!(
+            (month.start, month.end),
+            (date("2026-02-01"), date("2026-02-28"))
+        );
+        let month = ReportPeriod::last_complete(ReportKind::Monthly, date("2026-01-01"));
+        assert_eq!(
+            (month.start, month.end),
+            (date("2025-12-01"), date("2025-12-31"))
+        );
+    }
+
+    #[test]
+    fn test_build_report() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let row = |mac: &str, login_time: i64, minutes: i64, flow: f64| OnlineLogRow {
+            login_time,
+            logout_time: login_time + minutes * 60_000,
+            mac_address: mac.to_string(),
+            flow,
+            internet_down_flow: flow / 2.0,
+            chinanet_down_flow: flow / 2.0,
+            cost_money: 0.1,
+            time: minutes as f64,
+            ..Default::default()
+        };
+        // 1740787200000 是北京时间 2025-03-01 08:00
+        let rows = [
+            row("aa-bb-cc-00-00-01", 1740787200000, 90, 100.0),
+            row(
+                "11-22-33-44-55-66",
+                1740787200000 + 12 * 3_600_000,
+                30,
+                2048.0,
+            ),
+            row(
+                "AA:BB:CC:00:00:01",
+                1740787200000 + 24 * 3_600_000,
+                60,
+                300.0,
+            ),
+        ];
+        let aliases = [DeviceAlias {
+            mac: "AABBCC000001".to_string(),
+            name: "手机".to_string(),
+        }];
+        let bills = [MonthBill {
+            start_time: 1740758400000, // 2025-03-01 00:00
+            used_money: 3.5,
+            ..Default::default()
+        }];
+        let period = ReportPeriod {
+            kind: ReportKind::Monthly,
+            start: NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(),
+            end: NaiveDate::from_ymd_opt(2025, 3, 31).unwrap(),
+        };
+        let report = UsageReport::build("u1", period, &rows, &bills, &aliases, &offset);
+        assert_eq!(report.flow, 2448.0);
+        assert_eq!(report.ipv6_flow, 1224.0);
+        assert_eq!(report.minutes, 180.0);
+        assert_eq!(report.sessions, 3);
+        assert_eq!(report.bill.as_ref().map(|b| b.used_money), Some(3.5));
+        assert_eq!(report.top_devices[0].mac, "112233445566");
+        assert_eq!(report.top_devices[1].name.as_deref(), Some("手机"));
+        assert_eq!(report.top_devices[1].sessions, 2);
+        assert_eq!(report.peak_hours, [(8, 120.0), (9, 30.0), (20, 30.0)]);
+
+        let markdown = report.to_markdown();
+        assert!(markdown.starts_with("# u1 2025-03 月报\n"));
+        assert!(markdown.contains("| 手机 | AABBCC000001 | 400.00 MB | 2.5 小时 | 2 |"));
+        assert!(markdown.contains("| 08:00 - 09:00 | 2.0 小时 |"));
+        assert!(report.to_html().contains("<td>2.00 GB</td>"));
+    }
+
+    #[test]
+    fn test_failure_log() {
+        const HOUR: i64 = 3600;
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let period = ReportPeriod::last_complete(ReportKind::Weekly, date("2026-03-04"));
+        let mut log = FailureLog::default();
+        assert!(log.should_try("u1", &period, 0));
+        assert_eq!(log.record("u1", &period, 0), 1);
+        assert!(!log.should_try("u1", &period, HOUR - 1));
+        assert!(log.should_try("u1", &period, HOUR));
+        // 别的账号不受影响
+        assert!(log.should_try("u2", &period, 0));
+        assert_eq!(log.record("u1", &period, HOUR), 2);
+        assert!(!log.should_try("u1", &period, 2 * HOUR));
+        assert!(log.should_try("u1", &period, 3 * HOUR));
+
+        let mut now = 3 * HOUR;
+        while log.record("u1", &period, now) < MAX_ATTEMPTS {
+            now += 100 * HOUR;
+        }
+        assert!(!log.should_try("u1", &period, now + 100 * HOUR));
+
+        // 同一周期内不清掉，到下一周就清掉
+        log.prune(date("2026-03-08"));
+        assert!(!log.should_try("u1", &period, now + 100 * HOUR));
+        log.prune(date("2026-03-09"));
+        assert!(log.should_try("u1", &period, now));
+        log.record("u1", &period, 0);
+        log.clear("u1", &period);
+        assert!(log.should_try("u1", &period, 0));
+    }
+}
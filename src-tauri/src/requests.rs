@@ -1,4 +1,9 @@
-use std::{collections::HashMap, net::Ipv6Addr, sync::LazyLock, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::LazyLock,
+    time::Duration,
+};
 
 use anyhow::{Result, anyhow};
 use rand::RngExt;
@@ -10,7 +15,10 @@ use reqwest::{
 };
 use scraper::{Html, Selector};
 
-use crate::entities::{AmmeterData, MacList, UserType};
+use crate::{
+    entities::{AmmeterData, MacList, UserType},
+    utils::{interfaces::LoginBinding, webvpn::WebVpnProfile},
+};
 
 pub static CLIENT: LazyLock<Client> =
     LazyLock::new(|| Client::builder().no_proxy().build().unwrap_or_default());
@@ -36,6 +44,15 @@ pub static CLIENT: LazyLock<Client> =
 //     Ok(serde_json::from_str(json_str.unwrap())?)
 // }
 
+// 校园网后台地址，通过 WebVPN 登录时转换成对应的 WebVPN 地址
+fn zifuwu_url(user_type: &UserType, path: &str) -> Result<String> {
+    let url = format!("https://zifuwu.ustb.edu.cn{}", path);
+    match user_type {
+        UserType::Normal => Ok(url),
+        UserType::ViaVpn(profile) => profile.translate_up(&url),
+    }
+}
+
 // 获取登录页中的 check_code 用来提交 post 请求使用
 async fn get_check_code(res: reqwest::Response) -> Result<String> {
     let check_code_selector = Selector::parse("input[name=\"checkcode\"]").unwrap();
@@ -116,11 +133,24 @@ pub async fn simulate_login(
 }
 
 pub async fn simulate_login_via_vpn(
+    profile: &WebVpnProfile,
     account: &str,
     password: &str,
 ) -> Result<(Option<String>, Option<String>)> {
+    match login_webvpn(profile, account, password).await? {
+        Some(cookie_str) => login_zifuwu_via_vpn(profile, &cookie_str, account, password).await,
+        None => Ok((None, None)),
+    }
+}
+
+// 用本地密码登录 WebVPN 网关，返回网关的 cookie
+pub async fn login_webvpn(
+    profile: &WebVpnProfile,
+    account: &str,
+    password: &str,
+) -> Result<Option<String>> {
     // 访问 lib webvpn
-    let res = CLIENT.get("https://elib.ustb.edu.cn/login").send().await?;
+    let res = CLIENT.get(profile.gateway_url("/login")).send().await?;
     let res_header = res.headers().clone();
     let res_cookie = res_header.get_all(SET_COOKIE).iter().next();
     let cookie_str = res_cookie
@@ -139,14 +169,14 @@ pub async fn simulate_login_via_vpn(
     let captcha_id = Regex::new(r#"name="captcha_id" value="([^"]*)""#)?
         .captures(&res_text)
         .and_then(|cap| cap.get(1))
-        .unwrap()
+        .ok_or(anyhow!("There is no captcha_id in elib login ?!"))?
         .as_str();
     dbg!(captcha_id);
     // 发送登录请求
     let res = CLIENT
-        .post("https://elib.ustb.edu.cn/do-login")
+        .post(profile.gateway_url("/do-login"))
         .header("Cookie", cookie_str)
-        .header("Referer", "https://elib.ustb.edu.cn/login")
+        .header("Referer", profile.gateway_url("/login"))
         .header("Referrer-Policy", "strict-origin-when-cross-origin")
         .form(&[
             ("auth_type", "local"),
@@ -161,39 +191,51 @@ pub async fn simulate_login_via_vpn(
         .await?;
     // dbg!(res.text().await?);
     if res.text().await?.contains("用户名或密码错误") {
-        return Ok((None, None)); // 账号或密码出现错误！
+        return Ok(None); // 账号或密码出现错误！
     }
+    Ok(Some(cookie_str.to_string()))
+}
+
+// 已经登录 WebVPN 网关之后，通过网关登录校园网后台
+pub async fn login_zifuwu_via_vpn(
+    profile: &WebVpnProfile,
+    cookie_str: &str,
+    account: &str,
+    password: &str,
+) -> Result<(Option<String>, Option<String>)> {
     // 访问校园网后台登录页
-    let res = CLIENT.get("https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/login/")
-    .header("Cookie", cookie_str)
-    .send().await?;
+    let user_type = UserType::ViaVpn(profile.clone());
+    let login_url = zifuwu_url(&user_type, "/Self/login/")?;
+    let res = CLIENT
+        .get(&login_url)
+        .header("Cookie", cookie_str)
+        .send()
+        .await?;
     let check_code = get_check_code(res).await?;
     // dbg!(&check_code);
     tokio::time::sleep(Duration::from_millis(10)).await;
     // 获取用户名/密码错误3次以上的随机验证码（密码输错3次以内是隐藏的），需要带 cookie，这是必要的
     CLIENT
         .get(format!(
-            "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/login/randomCode?vpn-1&t={}",
+            "{}?vpn-1&t={}",
+            zifuwu_url(&user_type, "/Self/login/randomCode")?,
             rand::rng().random_range(0.0..1.0)
         ))
         .header(
             "accept",
             "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8",
         )
-        .header(
-            "cookie",
-            cookie_str
-        )
+        .header("cookie", cookie_str)
         .send()
         .await?;
     tokio::time::sleep(Duration::from_millis(10)).await;
     // 发送登录请求，携带 Cookie 和必要的 header，这样可以激活这个 cookie
     let response = CLIENT
-        .post("https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/login/verify")
+        .post(zifuwu_url(&user_type, "/Self/login/verify")?)
         .header("content-type", "application/x-www-form-urlencoded")
         .header("upgrade-insecure-requests", "1")
         .header("Cookie", cookie_str)
-        .header("Referer", "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/login/")
+        .header("Referer", &login_url)
         .header("Referrer-Policy", "strict-origin-when-cross-origin")
         .body(format!(
             "foo=&bar=&account={}&password={:x}&code=&checkcode={}",
@@ -217,12 +259,8 @@ pub async fn simulate_login_via_vpn(
 }
 
 // 用来获取 dashboard 页面一串奇怪的 user 信息，参考根目录 user-dashboard.json
-pub async fn get_user_dashboard(cookie_str: &str, user_type: UserType) -> Result<Option<String>> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/dashboard"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/dashboard"
-    };
+pub async fn get_user_dashboard(cookie_str: &str, user_type: &UserType) -> Result<Option<String>> {
+    let url = zifuwu_url(user_type, "/Self/dashboard")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let response = req.send().await?.text().await?;
     // dbg!(&response);
@@ -234,46 +272,29 @@ pub async fn get_user_dashboard(cookie_str: &str, user_type: UserType) -> Result
     Ok(res)
 }
 
-pub async fn get_online_list(cookie_str: &str, user_type: UserType) -> Result<String> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/dashboard/getOnlineList"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/dashboard/getOnlineList"
-    };
+pub async fn get_online_list(cookie_str: &str, user_type: &UserType) -> Result<String> {
+    let url = zifuwu_url(user_type, "/Self/dashboard/getOnlineList")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let json_str = req.send().await?.text().await?;
     Ok(json_str)
 }
 
-pub async fn get_login_history(cookie_str: &str, user_type: UserType) -> Result<String> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/dashboard/getLoginHistory"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/dashboard/getLoginHistory"
-    };
+pub async fn get_login_history(cookie_str: &str, user_type: &UserType) -> Result<String> {
+    let url = zifuwu_url(user_type, "/Self/dashboard/getLoginHistory")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let json_str = req.send().await?.text().await?;
     Ok(json_str)
 }
 
-pub async fn to_offline(cookie_str: &str, user_type: UserType, session_id: &str) -> Result<()> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/dashboard/tooffline"
-    } else {
-        "
-https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/dashboard/tooffline"
-    };
+pub async fn to_offline(cookie_str: &str, user_type: &UserType, session_id: &str) -> Result<()> {
+    let url = zifuwu_url(user_type, "/Self/dashboard/tooffline")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     req.query(&[("sessionid", session_id)]).send().await?;
     Ok(())
 }
 
-pub async fn get_month_pay(cookie_str: &str, year: u16, user_type: UserType) -> Result<String> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/bill/getMonthPay"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/bill/getMonthPay"
-    };
+pub async fn get_month_pay(cookie_str: &str, year: u16, user_type: &UserType) -> Result<String> {
+    let url = zifuwu_url(user_type, "/Self/bill/getMonthPay")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let json_str = req
         .query(&[("pageSize", 12), ("sortName", 0), ("year", year)])
@@ -285,22 +306,20 @@ pub async fn get_month_pay(cookie_str: &str, year: u16, user_type: UserType) ->
     Ok(json_str)
 }
 
-// 给定日期区间，获取用户此区间内所有使用数据
+// 给定日期区间，获取用户此区间内的使用数据，page_number 从 1 开始
 // start_date 2024-05-01 end_date 2024-05-31
 pub async fn get_user_online_log(
     cookie_str: &str,
     start_date: &str,
     end_date: &str,
-    user_type: UserType,
+    page_number: usize,
+    page_size: usize,
+    user_type: &UserType,
 ) -> Result<String> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/bill/getUserOnlineLog"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/bill/getUserOnlineLog"
-    };
+    let url = zifuwu_url(user_type, "/Self/bill/getUserOnlineLog")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let json_str = req
-        .query(&[("pageSize", 100000)])
+        .query(&[("pageNumber", page_number), ("pageSize", page_size)])
         .query(&[
             ("sortName", "loginTime"),
             ("sortOrder", "DESC"),
@@ -314,12 +333,8 @@ pub async fn get_user_online_log(
     Ok(json_str)
 }
 
-pub async fn get_mac_address(cookie_str: &str, user_type: UserType) -> Result<(MacList, String)> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/service/myMac"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/service/myMac"
-    };
+pub async fn get_mac_address(cookie_str: &str, user_type: &UserType) -> Result<(MacList, String)> {
+    let url = zifuwu_url(user_type, "/Self/service/myMac")?;
     // ajaxCsrfToken: 'a91fd92b-32c9-4867-bd70-297c76942f99'
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let res = req.send().await?.text().await?;
@@ -329,11 +344,7 @@ pub async fn get_mac_address(cookie_str: &str, user_type: UserType) -> Result<(M
         .and_then(|cap| Some(cap.get(1)?.as_str().to_owned()))
         .ok_or(anyhow!("ajaxCsrfToken not found"))?;
 
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/service/getMacList"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/service/getMacList"
-    };
+    let url = zifuwu_url(user_type, "/Self/service/getMacList")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     let res = req.send().await?.text().await?;
     let list = serde_json::from_str::<MacList>(&res)?;
@@ -342,16 +353,12 @@ pub async fn get_mac_address(cookie_str: &str, user_type: UserType) -> Result<(M
 
 pub async fn update_terminal_name(
     cookie_str: &str,
-    user_type: UserType,
+    user_type: &UserType,
     mac_address: &str,
     terminal_name: &str,
     ajax_csrf_token: &str,
 ) -> Result<String> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/service/updateTerminalName"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/service/updateTerminalName"
-    };
+    let url = zifuwu_url(user_type, "/Self/service/updateTerminalName")?;
 
     let req = CLIENT.post(url).header("Cookie", cookie_str);
     let response = req
@@ -369,15 +376,11 @@ pub async fn update_terminal_name(
 
 pub async fn unbind_mac(
     cookie_str: &str,
-    user_type: UserType,
+    user_type: &UserType,
     mac: &str,
     ajax_csrf_token: &str,
 ) -> Result<()> {
-    let url = if !matches!(user_type, UserType::ViaVpn) {
-        "https://zifuwu.ustb.edu.cn/Self/service/unbindmac"
-    } else {
-        "https://elib.ustb.edu.cn/https/77726476706e69737468656265737421eafe4789302526456d1c8be29d51367b8ada/Self/service/unbindmac"
-    };
+    let url = zifuwu_url(user_type, "/Self/service/unbindmac")?;
     let req = CLIENT.get(url).header("Cookie", cookie_str);
     req.query(&[("mac", mac), ("ajaxCsrfToken", ajax_csrf_token)])
         .send()
@@ -398,6 +401,16 @@ pub async fn get_address() -> Result<Vec<String>> {
     Ok(vec![v4_resp, v6_resp])
 }
 
+// 在线查询 IP 归属地，仅在离线表查不到且用户允许时使用
+pub async fn get_remote_ip_location(ip: &str) -> Result<String> {
+    let response = CLIENT
+        .get("https://api.mir6.com/api/ip_json")
+        .query(&[("ip", ip)])
+        .send()
+        .await?;
+    Ok(response.text().await?)
+}
+
 pub async fn get_ammeter(num: u32) -> Result<Option<i32>> {
     let response = CLIENT
         .post("http://fspapp.ustb.edu.cn/app.GouDian/index.jsp?m=alipay&c=AliPay&a=getDbYe")
@@ -414,22 +427,79 @@ pub async fn get_ammeter(num: u32) -> Result<Option<i32>> {
     }
 }
 
-pub async fn login_ustb_wifi(account: &str, password: &str) -> Result<()> {
-    let client = reqwest::Client::builder()
-        .redirect(redirect::Policy::none()) // 设置为不自动重定向
-        .build()?;
+// 绑定到选择的网卡或地址，能绑定网卡的系统上直接绑网卡
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn bind_client(
+    builder: reqwest::ClientBuilder,
+    binding: &LoginBinding,
+    ipv6: bool,
+) -> Option<reqwest::ClientBuilder> {
+    if let Some(interface) = &binding.interface {
+        return Some(builder.interface(interface));
+    }
+    bind_address(builder, binding, ipv6)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn bind_client(
+    builder: reqwest::ClientBuilder,
+    binding: &LoginBinding,
+    ipv6: bool,
+) -> Option<reqwest::ClientBuilder> {
+    bind_address(builder, binding, ipv6)
+}
+
+// 一个 client 只能绑一个源地址，IPv4 和 IPv6 的请求分开用两个 client
+fn bind_address(
+    builder: reqwest::ClientBuilder,
+    binding: &LoginBinding,
+    ipv6: bool,
+) -> Option<reqwest::ClientBuilder> {
+    let addr = if ipv6 {
+        IpAddr::V6(binding.ipv6?)
+    } else {
+        IpAddr::V4(binding.ipv4)
+    };
+    Some(builder.local_address(addr))
+}
+
+// 选择的网卡没有 IPv6 地址时返回 None，不让 IPv6 的请求从别的网卡发出去
+fn login_client(binding: Option<&LoginBinding>, ipv6: bool) -> Result<Option<Client>> {
+    let builder = reqwest::Client::builder().redirect(redirect::Policy::none()); // 设置为不自动重定向
+    let builder = match binding {
+        Some(binding) => match bind_client(builder, binding, ipv6) {
+            Some(builder) => builder,
+            None => return Ok(None),
+        },
+        None => builder,
+    };
+    Ok(Some(builder.build()?))
+}
+
+// binding 为 None 时由系统选择从哪个网卡发出去
+pub async fn login_ustb_wifi(
+    account: &str,
+    password: &str,
+    binding: Option<&LoginBinding>,
+) -> Result<()> {
+    // 后面的请求都是发到 IPv4 地址的
+    let client = login_client(binding, false)?.ok_or(anyhow!("没有可用的 IPv4 地址"))?;
+    let client_v6 = login_client(binding, true)?;
     // 第一次请求 login.ustb.edu.cn
     // 域名 login.ustb.edu.cn 有概率解析不到 ip 不知道为什么🧐，所以先尝试使用ip
+    // IPv6 的地址要从 IPv6 发出去，才能拿到本机的 IPv6 地址一起登录
     let login_urls = [
-        "http://[2001:da8:ad:3212::3]",
-        "http://202.204.48.82:80",
-        "http://login.ustb.edu.cn",
+        ("http://[2001:da8:ad:3212::3]", client_v6),
+        ("http://202.204.48.82:80", Some(client.clone())),
+        ("http://login.ustb.edu.cn", Some(client.clone())),
     ];
     let response = {
         let (tx, mut rx) = tokio::sync::mpsc::channel(3);
-        for url in login_urls {
+        for (url, client) in login_urls {
+            let Some(client) = client else {
+                continue;
+            };
             let url = url.to_string();
-            let client = client.clone();
             let tx = tx.clone();
             tokio::spawn(async move {
                 let res = client
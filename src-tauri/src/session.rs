@@ -0,0 +1,55 @@
+use std::fs;
+
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{get_vpn_session_path, webvpn::WebVpnProfile, write_private_file};
+
+// 网关不会告诉我们票据什么时候失效，超过这个时间就不再尝试恢复
+const SESSION_TTL_SECS: i64 = 24 * 60 * 60;
+
+// WebVPN 登录状态，单独存一个文件，不参与 OneDrive 同步
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VpnSession {
+    pub profile: WebVpnProfile,
+    pub user_name: String,
+    pub cookie_str: String,
+    pub expires_at: i64,
+}
+
+impl VpnSession {
+    pub fn new(profile: WebVpnProfile, user_name: String, cookie_str: String) -> Self {
+        VpnSession {
+            profile,
+            user_name,
+            cookie_str,
+            expires_at: Local::now().timestamp() + SESSION_TTL_SECS,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Local::now().timestamp() >= self.expires_at
+    }
+
+    pub fn load(app: &tauri::AppHandle) -> Result<Option<Self>> {
+        match fs::read(get_vpn_session_path(app)?) {
+            Ok(buf) => Ok(serde_json::from_slice(&buf).ok()),
+            // 没有该文件
+            Err(_) => Ok(None),
+        }
+    }
+
+    // 票据能直接登录 WebVPN，和其他凭据一样只让当前用户读
+    pub fn save(&self, app: &tauri::AppHandle) -> Result<()> {
+        write_private_file(&get_vpn_session_path(app)?, &serde_json::to_vec(&self)?)
+    }
+
+    pub fn clear(app: &tauri::AppHandle) -> Result<()> {
+        let path = get_vpn_session_path(app)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,51 @@
+pub mod account;
+pub mod device_alias;
+pub mod kick_policy;
+pub mod merge;
+pub mod report_schedule;
+
 use std::{
     fs::{self, File, OpenOptions},
     io::{Read, Write},
     path::PathBuf,
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{Result, anyhow};
+use chrono::Local;
 use serde::{Deserialize, Serialize};
 
-use crate::utils::get_config_path;
+use crate::{
+    sync::SyncBackendConfig,
+    utils::{get_config_path, webvpn::WebVpnProfile},
+};
+use account::{AccountPreferences, SavedAccount, deserialize_accounts, sort_accounts};
+use device_alias::DeviceAlias;
+use kick_policy::KickPolicy;
+use report_schedule::ReportSchedule;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Setting {
-    pub account: Vec<(String, String)>, // (username, password)
+    #[serde(default, deserialize_with = "deserialize_accounts")]
+    pub account: Vec<SavedAccount>,
     pub ammeter_number: Option<u32>,
     pub background_image_path: Option<String>,
     pub background_transparence: Option<u32>,
     pub background_blur: Option<u32>,
     pub collapsed: Option<bool>,
+    #[serde(default)]
+    pub webvpn_profiles: Vec<WebVpnProfile>,
+    pub webvpn_profile: Option<String>, // 当前使用的 WebVPN 配置名称
+    pub auto_sync: Option<bool>,
+    pub sync_backend: Option<SyncBackendConfig>, // 默认是 OneDrive
+    #[serde(default)]
+    pub device_aliases: Vec<DeviceAlias>, // 自己起的设备名，换学期重新绑定后可以一键恢复
+    #[serde(default)]
+    pub kick_policy: KickPolicy, // 自动下线规则，只对当前设备生效，不参与同步
+    // 登录校园网用的网卡名或者 IP 地址
+    // 不设置时不绑定，由系统选路由，自动检测到的校园网网卡只在界面上提示
+    pub login_interface: Option<String>,
+    #[serde(default)]
+    pub report_schedule: ReportSchedule, // 定时生成周报、月报，不参与同步
 }
 
 impl Setting {
@@ -46,29 +75,88 @@ impl Setting {
         Ok(())
     }
 
+    // 改了参与同步的设置（账号、电表号、WebVPN 配置、设备名）后用这个保存
+    // 开了自动同步的话，过一会儿同步
+    pub fn write_synced_setting(&self, app: &tauri::AppHandle) -> Result<()> {
+        self.write_setting(app)?;
+        crate::sync::schedule_sync(app);
+        Ok(())
+    }
+
+    // 登录成功后调用，记录登录时间，这样下次第一个显示的就是上次登录的账号（置顶的除外）
     pub fn set_account(&mut self, username: String, password: String) {
-        for (index, (saved_username, saved_password)) in self.account.iter_mut().enumerate() {
-            if saved_username == &username && saved_password == &password {
-                // 如果用户名和密码匹配，且没有修改密码
-                // 提升到最前面，这样下次获取账号信息，第一个显示就是上次登录的账号。
-                let item = self.account.remove(index);
-                self.account.insert(0, item);
-                return;
-            } else if saved_username == &username {
-                // 如果用户名匹配且密码有更改
-                *saved_password = password.clone();
-                // 提升到最前面
-                let item = self.account.remove(index);
-                self.account.insert(0, item);
-                return;
+        let now = Local::now().timestamp();
+        match self.account.iter_mut().find(|a| a.user_name == username) {
+            Some(account) => {
+                // 密码有更改
+                if account.password != password {
+                    account.password = password;
+                    account.modified = now;
+                }
+                account.last_used = now;
             }
+            // 系新账号
+            None => self.account.push(SavedAccount {
+                last_used: now,
+                modified: now,
+                ..SavedAccount::new(username, password)
+            }),
         }
-        // 系新账号
-        self.account.insert(0, (username, password));
+        sort_accounts(&mut self.account);
+    }
+
+    pub fn set_account_preferences(
+        &mut self,
+        username: &str,
+        preferences: AccountPreferences,
+    ) -> Result<()> {
+        let account = self
+            .account
+            .iter_mut()
+            .find(|a| a.user_name == username)
+            .ok_or_else(|| anyhow!("没有保存账号 {}", username))?;
+        account.preferences = preferences;
+        account.modified = Local::now().timestamp();
+        sort_accounts(&mut self.account);
+        Ok(())
     }
 
     pub fn has_local_account(&self, username: &str) -> bool {
-        self.account.iter().any(|(saved_username, _)| saved_username == username)
+        self.account.iter().any(|a| a.user_name == username)
+    }
+
+    // 只改保存的密码，不算登录
+    pub fn set_account_password(&mut self, username: &str, password: String) -> Result<()> {
+        let account = self
+            .account
+            .iter_mut()
+            .find(|a| a.user_name == username)
+            .ok_or_else(|| anyhow!("没有保存账号 {}", username))?;
+        account.password = password;
+        account.modified = Local::now().timestamp();
+        Ok(())
+    }
+
+    pub fn remove_account(&mut self, username: &str) -> Result<()> {
+        if !self.has_local_account(username) {
+            return Err(anyhow!("没有保存账号 {}", username));
+        }
+        self.account.retain(|a| a.user_name != username);
+        Ok(())
+    }
+
+    pub fn clear_accounts(&mut self) {
+        self.account.clear();
+    }
+
+    // 清空保存的账号密码和 WebDAV 密码，账号本身留着
+    pub fn clear_passwords(&mut self) {
+        for account in &mut self.account {
+            account.password.clear();
+        }
+        if let Some(SyncBackendConfig::WebDav { password, .. }) = &mut self.sync_backend {
+            password.clear();
+        }
     }
 
     pub fn set_ammeter_number(&mut self, ammeter_number: u32) {
@@ -113,7 +201,78 @@ impl Setting {
         self.background_blur = Some(background_blur);
     }
 
+    // 没有选择或者找不到时使用默认的北科 elib
+    pub fn current_webvpn_profile(&self) -> WebVpnProfile {
+        self.webvpn_profile
+            .as_ref()
+            .and_then(|name| self.webvpn_profiles.iter().find(|p| &p.name == name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // 同名的配置会被覆盖
+    pub fn set_webvpn_profile(&mut self, profile: WebVpnProfile) -> Result<()> {
+        profile.validate()?;
+        match self
+            .webvpn_profiles
+            .iter_mut()
+            .find(|p| p.name == profile.name)
+        {
+            Some(saved) => *saved = profile,
+            None => self.webvpn_profiles.push(profile),
+        }
+        Ok(())
+    }
+
+    pub fn remove_webvpn_profile(&mut self, name: &str) {
+        self.webvpn_profiles.retain(|p| p.name != name);
+        if self.webvpn_profile.as_deref() == Some(name) {
+            self.webvpn_profile = None;
+        }
+    }
+
+    pub fn select_webvpn_profile(&mut self, name: Option<String>) -> Result<()> {
+        if let Some(name) = &name
+            && !self.webvpn_profiles.iter().any(|p| &p.name == name)
+        {
+            return Err(anyhow!("没有名为 {} 的 WebVPN 配置", name));
+        }
+        self.webvpn_profile = name;
+        Ok(())
+    }
+
+    pub fn set_auto_sync(&mut self, auto_sync: bool) {
+        self.auto_sync = Some(auto_sync);
+    }
+
     pub fn set_collapsed(&mut self, collapsed: bool) {
         self.collapsed = Some(collapsed);
     }
+
+    // 返回是否有变化，name 为空时删除
+    pub fn set_device_alias(&mut self, mac: &str, name: &str) -> bool {
+        device_alias::set_alias(&mut self.device_aliases, mac, name)
+    }
+
+    pub fn set_login_interface(&mut self, login_interface: Option<String>) {
+        self.login_interface = login_interface.filter(|s| !s.trim().is_empty());
+    }
+
+    pub fn set_kick_policy(&mut self, policy: KickPolicy) -> Result<()> {
+        policy.validate()?;
+        self.kick_policy = policy;
+        Ok(())
+    }
+
+    pub fn set_report_schedule(&mut self, schedule: ReportSchedule) -> Result<()> {
+        if let Some(name) = schedule
+            .accounts
+            .iter()
+            .find(|name| !self.has_local_account(name))
+        {
+            return Err(anyhow!("没有保存账号 {}", name));
+        }
+        self.report_schedule = schedule;
+        Ok(())
+    }
 }
@@ -0,0 +1,126 @@
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LoginMode {
+    #[default]
+    Direct,
+    Vpn,
+}
+
+// 用户可以在界面上改的部分
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountPreferences {
+    pub display_name: Option<String>, // 比如 "实验室公用"
+    pub notes: Option<String>,
+    pub login_mode: LoginMode,
+    pub auto_login: bool,    // 是否可以被自动登录选中
+    pub pinned: Option<u32>, // 置顶顺序，越小越靠前
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedAccount {
+    pub user_name: String,
+    pub password: String,
+    #[serde(flatten)]
+    pub preferences: AccountPreferences,
+    #[serde(default)]
+    pub last_used: i64, // 上次登录的时间
+    #[serde(default)]
+    pub modified: i64, // 改密码或者改设置的时间，同步时后改的为准
+}
+
+impl SavedAccount {
+    pub fn new(user_name: String, password: String) -> Self {
+        SavedAccount {
+            user_name,
+            password,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AccountRepr {
+    // 旧版本存的是 (学号, 密码)
+    Legacy(String, String),
+    Current(SavedAccount),
+}
+
+// 兼容旧版本的配置文件
+pub fn deserialize_accounts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<SavedAccount>, D::Error> {
+    Ok(Vec::<AccountRepr>::deserialize(deserializer)?
+        .into_iter()
+        .map(|repr| match repr {
+            AccountRepr::Legacy(user_name, password) => SavedAccount::new(user_name, password),
+            AccountRepr::Current(account) => account,
+        })
+        .collect())
+}
+
+// 置顶的在前，其余的按上次登录时间，最近的在前
+pub fn sort_accounts(accounts: &mut [SavedAccount]) {
+    accounts.sort_by_key(|a| {
+        (
+            a.preferences.pinned.is_none(),
+            a.preferences.pinned,
+            Reverse(a.last_used),
+        )
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_accounts() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            #[serde(deserialize_with = "deserialize_accounts")]
+            account: Vec<SavedAccount>,
+        }
+        let res: Wrapper = serde_json::from_str(
+            r#"{"account": [["u1", "p1"], {"user_name": "u2", "password": "p2", "display_name": "lab", "login_mode": "vpn", "last_used": 5}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            res.account[0],
+            SavedAccount::new("u1".to_string(), "p1".to_string())
+        );
+        assert_eq!(res.account[1].preferences.login_mode, LoginMode::Vpn);
+        assert_eq!(
+            res.account[1].preferences.display_name.as_deref(),
+            Some("lab")
+        );
+        assert_eq!(res.account[1].last_used, 5);
+    }
+
+    #[test]
+    fn test_sort_accounts() {
+        let account = |name: &str, pinned: Option<u32>, last_used: i64| SavedAccount {
+            user_name: name.to_string(),
+            preferences: AccountPreferences {
+                pinned,
+                ..Default::default()
+            },
+            last_used,
+            ..Default::default()
+        };
+        let mut accounts = vec![
+            account("a", None, 1),
+            account("b", Some(2), 0),
+            account("c", None, 3),
+            account("d", Some(1), 0),
+        ];
+        sort_accounts(&mut accounts);
+        let names: Vec<_> = accounts.iter().map(|a| a.user_name.as_str()).collect();
+        assert_eq!(names, ["d", "b", "c", "a"]);
+    }
+}
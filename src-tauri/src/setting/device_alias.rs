@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utils::oui::normalize_mac;
+
+// 本地记住的设备名，按 MAC 存，解绑再绑定后可以重新设置到校园网后台
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceAlias {
+    pub mac: String, // 不带分隔符的大写
+    pub name: String,
+}
+
+// name 为空时删除，返回是否有变化
+pub fn set_alias(aliases: &mut Vec<DeviceAlias>, mac: &str, name: &str) -> bool {
+    let mac = normalize_mac(mac);
+    let name = name.trim();
+    match aliases.iter().position(|a| a.mac == mac) {
+        Some(i) if name.is_empty() => {
+            aliases.remove(i);
+            true
+        }
+        Some(i) if aliases[i].name != name => {
+            aliases[i].name = name.to_string();
+            true
+        }
+        Some(_) => false,
+        None if name.is_empty() => false,
+        None => {
+            aliases.push(DeviceAlias {
+                mac,
+                name: name.to_string(),
+            });
+            true
+        }
+    }
+}
+
+pub fn find_alias<'a>(aliases: &'a [DeviceAlias], mac: &str) -> Option<&'a str> {
+    let mac = normalize_mac(mac);
+    aliases
+        .iter()
+        .find(|a| a.mac == mac)
+        .map(|a| a.name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_alias() {
+        let mut aliases = vec![];
+        assert!(set_alias(&mut aliases, "aa:bb:cc:dd:ee:ff", " 手机 "));
+        assert_eq!(find_alias(&aliases, "AABBCCDDEEFF"), Some("手机"));
+        assert!(!set_alias(&mut aliases, "AABBCCDDEEFF", "手机"));
+        assert!(set_alias(&mut aliases, "AABBCCDDEEFF", "平板"));
+        assert_eq!(aliases.len(), 1);
+        assert!(set_alias(&mut aliases, "AABBCCDDEEFF", ""));
+        assert!(aliases.is_empty());
+        assert!(!set_alias(&mut aliases, "AABBCCDDEEFF", ""));
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+// 后台检查的间隔，太频繁了校园网后台会限制
+pub const MIN_INTERVAL_MINUTES: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum KickRule {
+    // 当前设备永远不下线，优先于其他规则
+    KeepThisDevice,
+    // MAC 地址不在名单里的下线
+    KickNotInList { list: String },
+    // 超过这么多小时没有流量的下线
+    KickIdle { hours: u32 },
+}
+
+// 一组自己的设备，比如 "我的设备"、"室友的设备"
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceList {
+    pub name: String,
+    pub macs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KickPolicy {
+    pub enabled: bool, // 是否在后台定时检查
+    pub dry_run: bool, // 只报告会下线哪些设备，不真的下线
+    pub interval_minutes: u32,
+    pub rules: Vec<KickRule>,
+    pub device_lists: Vec<DeviceList>,
+}
+
+impl Default for KickPolicy {
+    fn default() -> Self {
+        KickPolicy {
+            enabled: false,
+            dry_run: true,
+            interval_minutes: 10,
+            rules: vec![KickRule::KeepThisDevice],
+            device_lists: vec![],
+        }
+    }
+}
+
+impl KickPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_minutes < MIN_INTERVAL_MINUTES {
+            return Err(anyhow!("检查间隔不能少于 {} 分钟", MIN_INTERVAL_MINUTES));
+        }
+        for rule in &self.rules {
+            match rule {
+                KickRule::KickNotInList { list } if self.device_list(list).is_none() => {
+                    return Err(anyhow!("没有名为 {} 的设备名单", list));
+                }
+                KickRule::KickIdle { hours: 0 } => {
+                    return Err(anyhow!("空闲时间至少为 1 小时"));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn device_list(&self, name: &str) -> Option<&DeviceList> {
+        self.device_lists.iter().find(|l| l.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut policy = KickPolicy {
+            rules: serde_json::from_str(
+                r#"[{"kind": "keepThisDevice"}, {"kind": "kickNotInList", "list": "mine"}, {"kind": "kickIdle", "hours": 6}]"#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        policy.device_lists.push(DeviceList {
+            name: "mine".to_string(),
+            macs: vec![],
+        });
+        assert!(policy.validate().is_ok());
+        policy.interval_minutes = 1;
+        assert!(policy.validate().is_err());
+    }
+}
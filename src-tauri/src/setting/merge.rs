@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Setting,
+    account::{SavedAccount, deserialize_accounts, sort_accounts},
+    device_alias::DeviceAlias,
+};
+use crate::utils::webvpn::WebVpnProfile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+// 两边都改了同一项，记录下来告诉用户最后用的是哪边
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conflict {
+    pub field: String,
+    pub key: Option<String>, // 列表项的名字，比如账号的学号
+    pub resolution: Side,
+}
+
+// 三方合并，base 是上次同步后的配置，没有就当作两边都是新加的
+// 背景图片、折叠侧边栏、自动同步等只和当前设备有关的设置一律保留本地的
+pub fn merge(
+    base: Option<&Setting>,
+    local: &Setting,
+    remote: &Setting,
+    prefer_local: bool,
+) -> (Setting, Vec<Conflict>) {
+    let mut conflicts = vec![];
+    let mut res = local.clone();
+
+    // 账号按学号合并，两边都改了密码或设置时按各自的修改时间，后改的为准
+    // 登录时间每次登录都会变，不算修改，合并完取两边最新的
+    let last_used = |name: &str| {
+        let find = |s: &Setting| {
+            s.account
+                .iter()
+                .find(|a| a.user_name == name)
+                .map_or(0, |a| a.last_used)
+        };
+        find(local).max(find(remote))
+    };
+    res.account = merge_keyed(
+        "account",
+        base.map(|b| without_last_used(&b.account)).as_deref(),
+        &without_last_used(&local.account),
+        &without_last_used(&remote.account),
+        |a| &a.user_name,
+        |l| {
+            let remote_time = remote
+                .account
+                .iter()
+                .find(|r| r.user_name == l.user_name)
+                .map_or(0, |r| r.modified);
+            l.modified >= remote_time
+        },
+        &mut conflicts,
+    );
+    for account in &mut res.account {
+        account.last_used = last_used(&account.user_name);
+    }
+    sort_accounts(&mut res.account);
+
+    res.ammeter_number = merge_value(
+        "ammeter_number",
+        base.map(|b| &b.ammeter_number),
+        &local.ammeter_number,
+        &remote.ammeter_number,
+        prefer_local,
+        &mut conflicts,
+    );
+    res.webvpn_profiles = merge_keyed(
+        "webvpn_profiles",
+        base.map(|b| b.webvpn_profiles.as_slice()),
+        &local.webvpn_profiles,
+        &remote.webvpn_profiles,
+        |p| &p.name,
+        |_| prefer_local,
+        &mut conflicts,
+    );
+    res.webvpn_profile = merge_value(
+        "webvpn_profile",
+        base.map(|b| &b.webvpn_profile),
+        &local.webvpn_profile,
+        &remote.webvpn_profile,
+        prefer_local,
+        &mut conflicts,
+    );
+    // 选中的配置被另一边删掉了
+    if let Some(name) = &res.webvpn_profile
+        && !res.webvpn_profiles.iter().any(|p| &p.name == name)
+    {
+        res.webvpn_profile = None;
+    }
+    res.device_aliases = merge_keyed(
+        "device_aliases",
+        base.map(|b| b.device_aliases.as_slice()),
+        &local.device_aliases,
+        &remote.device_aliases,
+        |a| &a.mac,
+        |_| prefer_local,
+        &mut conflicts,
+    );
+
+    (res, conflicts)
+}
+
+// 参与同步的设置，上传到远端和记录同步基准时只存这些
+// 同步目标的凭据、自动下线规则、背景图片等只和当前设备有关，不上传
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncedSetting {
+    #[serde(default, deserialize_with = "deserialize_accounts")]
+    pub account: Vec<SavedAccount>,
+    pub ammeter_number: Option<u32>,
+    #[serde(default)]
+    pub webvpn_profiles: Vec<WebVpnProfile>,
+    pub webvpn_profile: Option<String>,
+    #[serde(default)]
+    pub device_aliases: Vec<DeviceAlias>,
+}
+
+impl From<&Setting> for SyncedSetting {
+    fn from(setting: &Setting) -> Self {
+        SyncedSetting {
+            account: setting.account.clone(),
+            ammeter_number: setting.ammeter_number,
+            webvpn_profiles: setting.webvpn_profiles.clone(),
+            webvpn_profile: setting.webvpn_profile.clone(),
+            device_aliases: setting.device_aliases.clone(),
+        }
+    }
+}
+
+impl From<SyncedSetting> for Setting {
+    // 其他设置用默认值，合并时都以本地的为准
+    fn from(synced: SyncedSetting) -> Self {
+        Setting {
+            account: synced.account,
+            ammeter_number: synced.ammeter_number,
+            webvpn_profiles: synced.webvpn_profiles,
+            webvpn_profile: synced.webvpn_profile,
+            device_aliases: synced.device_aliases,
+            ..Default::default()
+        }
+    }
+}
+
+// 只比较参与同步的设置
+pub fn synced_eq(a: &Setting, b: &Setting) -> bool {
+    SyncedSetting::from(a) == SyncedSetting::from(b)
+}
+
+fn without_last_used(accounts: &[SavedAccount]) -> Vec<SavedAccount> {
+    accounts
+        .iter()
+        .map(|a| SavedAccount {
+            last_used: 0,
+            ..a.clone()
+        })
+        .collect()
+}
+
+fn merge_value<T: PartialEq + Clone>(
+    field: &str,
+    base: Option<&T>,
+    local: &T,
+    remote: &T,
+    prefer_local: bool,
+    conflicts: &mut Vec<Conflict>,
+) -> T {
+    if local == remote || base == Some(remote) {
+        return local.clone();
+    }
+    if base == Some(local) {
+        return remote.clone();
+    }
+    let resolution = if prefer_local {
+        Side::Local
+    } else {
+        Side::Remote
+    };
+    conflicts.push(Conflict {
+        field: field.to_string(),
+        key: None,
+        resolution,
+    });
+    match resolution {
+        Side::Local => local.clone(),
+        Side::Remote => remote.clone(),
+    }
+}
+
+// 按 key 合并列表，本地的顺序在前，只有远端有的接在后面
+// 一边删掉、另一边没动的就删掉；一边删掉、另一边改了的保留修改
+fn merge_keyed<T: PartialEq + Clone>(
+    field: &str,
+    base: Option<&[T]>,
+    local: &[T],
+    remote: &[T],
+    key: impl Fn(&T) -> &String,
+    prefer_local: impl Fn(&T) -> bool,
+    conflicts: &mut Vec<Conflict>,
+) -> Vec<T> {
+    let find = |list: &'_ [T], k: &String| list.iter().find(|item| key(item) == k).cloned();
+    let mut keys: Vec<String> = local.iter().map(|item| key(item).clone()).collect();
+    for item in remote {
+        if !keys.contains(key(item)) {
+            keys.push(key(item).clone());
+        }
+    }
+
+    let mut res = vec![];
+    for k in keys {
+        let b = base.and_then(|base| find(base, &k));
+        let item = match (find(local, &k), find(remote, &k)) {
+            (Some(l), Some(r)) => {
+                if l == r || b.as_ref() == Some(&r) {
+                    Some(l)
+                } else if b.as_ref() == Some(&l) {
+                    Some(r)
+                } else {
+                    let resolution = if prefer_local(&l) {
+                        Side::Local
+                    } else {
+                        Side::Remote
+                    };
+                    conflicts.push(Conflict {
+                        field: field.to_string(),
+                        key: Some(k.clone()),
+                        resolution,
+                    });
+                    match resolution {
+                        Side::Local => Some(l),
+                        Side::Remote => Some(r),
+                    }
+                }
+            }
+            (Some(l), None) => match &b {
+                Some(b) if *b == l => None,
+                Some(_) => {
+                    conflicts.push(Conflict {
+                        field: field.to_string(),
+                        key: Some(k.clone()),
+                        resolution: Side::Local,
+                    });
+                    Some(l)
+                }
+                None => Some(l),
+            },
+            (None, Some(r)) => match &b {
+                Some(b) if *b == r => None,
+                Some(_) => {
+                    conflicts.push(Conflict {
+                        field: field.to_string(),
+                        key: Some(k.clone()),
+                        resolution: Side::Remote,
+                    });
+                    Some(r)
+                }
+                None => Some(r),
+            },
+            (None, None) => None,
+        };
+        res.extend(item);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(name: &str, password: &str) -> SavedAccount {
+        SavedAccount::new(name.to_string(), password.to_string())
+    }
+
+    fn names(accounts: &[SavedAccount]) -> Vec<(&str, &str)> {
+        accounts
+            .iter()
+            .map(|a| (a.user_name.as_str(), a.password.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_accounts() {
+        let base = Setting {
+            account: vec![account("a", "1"), account("b", "1"), account("c", "1")],
+            ..Default::default()
+        };
+        let mut local = base.clone();
+        local.account = vec![account("d", "1"), account("a", "2"), account("b", "1")];
+        local.account[1].modified = 100;
+        local.account[1].last_used = 300;
+        let mut remote = base.clone();
+        remote.account = vec![account("a", "3"), account("e", "1"), account("c", "1")];
+        remote.account[0].modified = 200;
+        // 只是登录过，不算修改
+        remote.account[2].last_used = 50;
+
+        let (res, conflicts) = merge(Some(&base), &local, &remote, true);
+        // c 本地删了，b 远端删了，a 远端改得更晚，a 最近登录过排在前面
+        assert_eq!(
+            names(&res.account),
+            vec![("a", "3"), ("d", "1"), ("e", "1")]
+        );
+        assert_eq!(res.account[0].modified, 200);
+        assert_eq!(res.account[0].last_used, 300);
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                field: "account".to_string(),
+                key: Some("a".to_string()),
+                resolution: Side::Remote,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_merge_fields() {
+        let base = Setting {
+            ammeter_number: Some(1),
+            background_blur: Some(10),
+            ..Default::default()
+        };
+        let mut local = base.clone();
+        local.background_blur = Some(20);
+        let mut remote = base.clone();
+        remote.ammeter_number = Some(2);
+        remote.background_blur = Some(30);
+        remote.background_image_path = Some("/remote/bg.png".to_string());
+
+        let (res, conflicts) = merge(Some(&base), &local, &remote, false);
+        assert_eq!(res.ammeter_number, Some(2));
+        assert_eq!(res.background_blur, Some(20));
+        assert_eq!(res.background_image_path, None);
+        assert!(conflicts.is_empty());
+
+        local.ammeter_number = Some(3);
+        let (res, conflicts) = merge(Some(&base), &local, &remote, false);
+        assert_eq!(res.ammeter_number, Some(2));
+        assert_eq!(conflicts[0].field, "ammeter_number");
+
+        // 第一次同步没有 base，账号取并集
+        local.account = vec![account("a", "1")];
+        remote.account = vec![account("b", "1")];
+        let (res, _) = merge(None, &local, &remote, true);
+        assert_eq!(names(&res.account), vec![("a", "1"), ("b", "1")]);
+
+        // 设备名按 MAC 合并
+        local.set_device_alias("AABBCC000001", "手机");
+        remote.set_device_alias("AABBCC000002", "平板");
+        let (res, _) = merge(Some(&base), &local, &remote, true);
+        assert_eq!(res.device_aliases.len(), 2);
+    }
+}
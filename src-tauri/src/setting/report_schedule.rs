@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+// 定时生成使用报告，只在当前设备上生成，不参与同步
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReportSchedule {
+    pub weekly: bool,  // 每周一生成上一周的
+    pub monthly: bool, // 每月一号生成上个月的
+    pub format: ReportFormat,
+    pub notify: bool,          // 生成后弹出系统通知
+    pub accounts: Vec<String>, // 为空时给所有保存了密码的账号生成
+}
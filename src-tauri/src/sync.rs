@@ -0,0 +1,688 @@
+pub mod bundle;
+pub mod folder;
+pub mod webdav;
+
+use std::{
+    fmt, fs,
+    future::Future,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use sha2::{Digest, Sha256};
+use tauri::{Emitter, Manager};
+use tokio::sync::Mutex;
+
+use crate::{
+    entities::AppState,
+    events::{self, AppEvent},
+    onedrive::OneDriveBackend,
+    setting::{
+        Setting,
+        merge::{self, Conflict, SyncedSetting},
+    },
+    utils::{get_config_path, get_store_path, get_sync_state_path, write_private_file},
+};
+use folder::FolderBackend;
+use webdav::WebDavBackend;
+
+const SETTING_FILE: &str = "setting.txt";
+// 修改配置后等这么久再同步，连续修改只同步一次
+const SYNC_DELAY_SECS: u64 = 5;
+
+static SYNC_GENERATION: AtomicU64 = AtomicU64::new(0);
+// 启动时的同步和修改配置触发的同步不能同时进行
+static SYNC_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+// 返回给前端的错误，前端按 kind 区分处理
+#[derive(Debug)]
+pub enum SyncError {
+    Network(String),
+    // 用户在登录页面点了取消
+    Cancelled,
+    // 凭据失效，需要重新登录
+    Auth(String),
+    QuotaExceeded,
+    Remote {
+        status: Option<u16>,
+        code: String,
+        message: String,
+    },
+    InvalidData(String),
+    Other(String),
+}
+
+impl SyncError {
+    fn kind(&self) -> &'static str {
+        match self {
+            SyncError::Network(_) => "network",
+            SyncError::Cancelled => "cancelled",
+            SyncError::Auth(_) => "auth",
+            SyncError::QuotaExceeded => "quotaExceeded",
+            SyncError::Remote { .. } => "remote",
+            SyncError::InvalidData(_) => "invalidData",
+            SyncError::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Network(e) => write!(f, "网络错误：{e}"),
+            SyncError::Cancelled => write!(f, "已取消登录"),
+            SyncError::Auth(msg) | SyncError::InvalidData(msg) | SyncError::Other(msg) => {
+                write!(f, "{msg}")
+            }
+            SyncError::QuotaExceeded => write!(f, "存储空间已满"),
+            SyncError::Remote {
+                status: Some(status),
+                code,
+                message,
+            } => write!(f, "服务器返回错误 {status} {code}：{message}"),
+            SyncError::Remote {
+                status: None,
+                code,
+                message,
+            } => write!(f, "服务器返回错误 {code}：{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+// 函数内部都用 anyhow，到命令这一层再还原出具体的错误
+impl From<anyhow::Error> for SyncError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<SyncError>() {
+            Ok(e) => e,
+            Err(e) => match e.downcast::<reqwest::Error>() {
+                Ok(e) => SyncError::Network(e.to_string()),
+                Err(e) => SyncError::Other(e.to_string()),
+            },
+        }
+    }
+}
+
+impl Serialize for SyncError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SyncError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+// 远端文件的版本信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteMeta {
+    pub version: String, // 文件有修改就会变，ETag 或者修改时间
+    pub modified: Option<DateTime<Utc>>,
+}
+
+// 同步目标，只需要按文件名存取
+pub trait SyncBackend: Send + Sync {
+    // 文件不存在时返回 None
+    fn metadata(&self, name: &str) -> impl Future<Output = Result<Option<RemoteMeta>>> + Send;
+    fn get(&self, name: &str) -> impl Future<Output = Result<Vec<u8>>> + Send;
+    // 返回上传后的版本信息
+    fn put(&self, name: &str, data: Vec<u8>) -> impl Future<Output = Result<RemoteMeta>> + Send;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SyncBackendConfig {
+    #[default]
+    OneDrive,
+    WebDav {
+        url: String, // 存放配置文件的文件夹地址
+        username: String,
+        password: String,
+    },
+    Folder {
+        path: String,
+    },
+}
+
+impl SyncBackendConfig {
+    // 换了同步目标就不能沿用上次的同步状态
+    fn id(&self) -> String {
+        match self {
+            SyncBackendConfig::OneDrive => "onedrive".to_string(),
+            SyncBackendConfig::WebDav { url, username, .. } => format!("webdav:{username}@{url}"),
+            SyncBackendConfig::Folder { path } => format!("folder:{path}"),
+        }
+    }
+}
+
+// 上次同步完成时两边的状态，用来判断这次谁有修改
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncState {
+    backend: String,
+    remote_version: String,
+    local_hash: String, // 本地参与同步的配置的 sha256
+    #[serde(default)]
+    base: Option<SyncedSetting>, // 同步后的配置，作为下次合并的基准
+    #[serde(default)]
+    data_version: Option<String>, // 电费记录压缩包的远端版本
+    #[serde(default)]
+    data_hash: Option<String>,
+}
+
+impl SyncState {
+    fn load(app: &tauri::AppHandle, backend: &str) -> Result<Option<Self>> {
+        match fs::read(get_sync_state_path(app)?) {
+            Ok(buf) => Ok(serde_json::from_slice::<Self>(&buf)
+                .ok()
+                .filter(|state| state.backend == backend)),
+            // 没有该文件
+            Err(_) => Ok(None),
+        }
+    }
+
+    // 里面有账号密码，和 OneDrive 凭据一样只让当前用户读
+    fn save(&self, app: &tauri::AppHandle) -> Result<()> {
+        write_private_file(&get_sync_state_path(app)?, &serde_json::to_vec(&self)?)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub message: String,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SyncDirection {
+    Upload,
+    // 下载后和本地合并，合并结果跟远端不同再上传
+    Merge,
+    UpToDate,
+}
+
+// 跟上次同步时比较，远端没改过就直接上传，否则都要合并
+fn decide_direction(
+    remote_version: Option<&str>,
+    local_hash: &str,
+    last_sync: Option<&SyncState>,
+) -> SyncDirection {
+    let Some(remote_version) = remote_version else {
+        return SyncDirection::Upload;
+    };
+    let Some(last_sync) = last_sync else {
+        return SyncDirection::Merge;
+    };
+    let remote_changed = last_sync.remote_version != remote_version;
+    let local_changed = last_sync.local_hash != local_hash;
+    match (local_changed, remote_changed) {
+        (false, false) => SyncDirection::UpToDate,
+        (true, false) => SyncDirection::Upload,
+        (_, true) => SyncDirection::Merge,
+    }
+}
+
+// 只算参与同步的设置，改背景、折叠侧边栏这些不算本地有修改
+fn setting_hash(setting: &Setting) -> Result<String> {
+    Ok(hex::encode(Sha256::digest(serde_json::to_vec(
+        &SyncedSetting::from(setting),
+    )?)))
+}
+
+// 远端存的是 base64 过的 json，和以前上传到 OneDrive 的格式一致，但只有参与同步的设置
+fn encode_setting(setting: &Setting) -> Result<Vec<u8>> {
+    Ok(URL_SAFE
+        .encode(serde_json::to_vec(&SyncedSetting::from(setting))?)
+        .into_bytes())
+}
+
+// 旧版本上传的是整个配置，多出来的字段直接忽略
+fn decode_setting(data: &[u8]) -> Result<Setting> {
+    let data = URL_SAFE
+        .decode(data.trim_ascii())
+        .map_err(|e| SyncError::InvalidData(format!("解码base64错误：{e:?}")))?;
+    let synced: SyncedSetting = serde_json::from_slice(&data)
+        .map_err(|e| SyncError::InvalidData(format!("配置文件格式不正确！{e:?}")))?;
+    Ok(synced.into())
+}
+
+// 按配置创建的同步目标
+enum AnyBackend {
+    OneDrive(OneDriveBackend),
+    WebDav(WebDavBackend),
+    Folder(FolderBackend),
+}
+
+impl AnyBackend {
+    async fn open(app: &tauri::AppHandle, config: &SyncBackendConfig) -> Result<Self> {
+        Ok(match config {
+            SyncBackendConfig::OneDrive => AnyBackend::OneDrive(OneDriveBackend::new(app).await?),
+            SyncBackendConfig::WebDav {
+                url,
+                username,
+                password,
+            } => AnyBackend::WebDav(WebDavBackend::new(url, username, password)?),
+            SyncBackendConfig::Folder { path } => AnyBackend::Folder(FolderBackend::new(path)),
+        })
+    }
+}
+
+impl SyncBackend for AnyBackend {
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>> {
+        match self {
+            AnyBackend::OneDrive(b) => b.metadata(name).await,
+            AnyBackend::WebDav(b) => b.metadata(name).await,
+            AnyBackend::Folder(b) => b.metadata(name).await,
+        }
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        match self {
+            AnyBackend::OneDrive(b) => b.get(name).await,
+            AnyBackend::WebDav(b) => b.get(name).await,
+            AnyBackend::Folder(b) => b.get(name).await,
+        }
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteMeta> {
+        match self {
+            AnyBackend::OneDrive(b) => b.put(name, data).await,
+            AnyBackend::WebDav(b) => b.put(name, data).await,
+            AnyBackend::Folder(b) => b.put(name, data).await,
+        }
+    }
+}
+
+async fn current_backend(app: &tauri::AppHandle) -> Result<(AnyBackend, String)> {
+    let config = app
+        .state::<AppState>()
+        .setting
+        .read()
+        .await
+        .sync_backend
+        .clone()
+        .unwrap_or_default();
+    Ok((AnyBackend::open(app, &config).await?, config.id()))
+}
+
+// 按配置选择同步目标，默认是 OneDrive，结果也通过 SyncFinished 事件发给前端
+pub async fn sync(app: &tauri::AppHandle, automatic: bool) -> Result<SyncReport> {
+    let _guard = SYNC_LOCK.lock().await;
+    let res: Result<SyncReport> = async {
+        let (backend, backend_id) = current_backend(app).await?;
+        sync_with(app, &backend, &backend_id).await
+    }
+    .await;
+    events::emit(
+        app,
+        match &res {
+            Ok(report) => AppEvent::SyncFinished {
+                message: report.message.clone(),
+                error: None,
+                automatic,
+            },
+            Err(e) => AppEvent::SyncFinished {
+                message: "同步失败".to_string(),
+                error: Some(e.to_string()),
+                automatic,
+            },
+        },
+    );
+    res
+}
+
+// 不合并，直接用本地配置覆盖远端，清除账号之后用，不然下次同步又会合并回来
+// 上次同步的状态里也存着账号密码，覆盖失败也要删掉。没同步过返回 false
+pub async fn overwrite_remote(app: &tauri::AppHandle) -> Result<bool> {
+    let _guard = SYNC_LOCK.lock().await;
+    let state_path = get_sync_state_path(app)?;
+    if !state_path.exists() {
+        return Ok(false);
+    }
+    let res: Result<()> = async {
+        let (backend, backend_id) = current_backend(app).await?;
+        let setting = app.state::<AppState>().setting.read().await.clone();
+        let remote_version = backend
+            .put(SETTING_FILE, encode_setting(&setting)?)
+            .await?
+            .version;
+        let last_sync = SyncState::load(app, &backend_id)?;
+        SyncState {
+            backend: backend_id,
+            remote_version,
+            local_hash: setting_hash(&setting)?,
+            base: Some(SyncedSetting::from(&setting)),
+            data_version: last_sync.as_ref().and_then(|s| s.data_version.clone()),
+            data_hash: last_sync.and_then(|s| s.data_hash),
+        }
+        .save(app)
+    }
+    .await;
+    if res.is_err() {
+        let _ = fs::remove_file(&state_path);
+    }
+    res.map(|_| true)
+}
+
+async fn sync_with<B: SyncBackend>(
+    app: &tauri::AppHandle,
+    backend: &B,
+    backend_id: &str,
+) -> Result<SyncReport> {
+    let last_sync = SyncState::load(app, backend_id)?;
+    let (mut new_state, mut report) =
+        sync_setting_file(app, backend, backend_id, last_sync.as_ref()).await?;
+    if sync_data(app, backend, last_sync.as_ref(), &mut new_state).await? {
+        report.message += "，电费记录已同步";
+    }
+    new_state.save(app)?;
+    Ok(report)
+}
+
+async fn sync_setting_file<B: SyncBackend>(
+    app: &tauri::AppHandle,
+    backend: &B,
+    backend_id: &str,
+    last_sync: Option<&SyncState>,
+) -> Result<(SyncState, SyncReport)> {
+    let remote_meta = backend.metadata(SETTING_FILE).await?;
+    let setting = app.state::<AppState>().setting.read().await.clone();
+    let local_hash = setting_hash(&setting)?;
+
+    let res = match decide_direction(
+        remote_meta.as_ref().map(|m| m.version.as_str()),
+        &local_hash,
+        last_sync,
+    ) {
+        SyncDirection::Upload => (
+            SyncState {
+                backend: backend_id.to_string(),
+                remote_version: backend
+                    .put(SETTING_FILE, encode_setting(&setting)?)
+                    .await?
+                    .version,
+                local_hash,
+                base: Some(SyncedSetting::from(&setting)),
+                data_version: None,
+                data_hash: None,
+            },
+            SyncReport {
+                message: "已上传配置文件".to_string(),
+                conflicts: vec![],
+            },
+        ),
+        SyncDirection::Merge => {
+            let remote = decode_setting(&backend.get(SETTING_FILE).await?)?;
+            // 两边改了同一项时，按整个文件的修改时间决定用哪边
+            let local_modified = fs::metadata(get_config_path(app)?)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(DateTime::<Utc>::from);
+            let prefer_local = match (
+                remote_meta.as_ref().and_then(|m| m.modified),
+                local_modified,
+            ) {
+                (Some(remote), Some(local)) => local > remote,
+                _ => true,
+            };
+            let base = last_sync.and_then(|s| s.base.clone()).map(Setting::from);
+            let (merged, conflicts) = merge::merge(base.as_ref(), &setting, &remote, prefer_local);
+
+            let merged_hash = setting_hash(&merged)?;
+            if merged_hash != local_hash {
+                *app.state::<AppState>().setting.write().await = merged.clone();
+                merged.write_setting(app)?;
+                // 前端需要重新加载配置
+                let _ = app.emit("setting_synced", ());
+            }
+            let remote_version = match remote_meta {
+                Some(meta) if merge::synced_eq(&merged, &remote) => meta.version,
+                _ => {
+                    backend
+                        .put(SETTING_FILE, encode_setting(&merged)?)
+                        .await?
+                        .version
+                }
+            };
+            (
+                SyncState {
+                    backend: backend_id.to_string(),
+                    remote_version,
+                    local_hash: merged_hash,
+                    base: Some(SyncedSetting::from(&merged)),
+                    data_version: None,
+                    data_hash: None,
+                },
+                SyncReport {
+                    message: "已和远端的配置文件合并".to_string(),
+                    conflicts,
+                },
+            )
+        }
+        SyncDirection::UpToDate => (
+            last_sync.cloned().ok_or(anyhow!("找不到上次同步的记录"))?,
+            SyncReport {
+                message: "配置文件已是最新".to_string(),
+                conflicts: vec![],
+            },
+        ),
+    };
+    Ok(res)
+}
+
+// 电费记录打包成一个压缩包同步，下载时按日期合并，返回这次有没有改动
+async fn sync_data<B: SyncBackend>(
+    app: &tauri::AppHandle,
+    backend: &B,
+    last_sync: Option<&SyncState>,
+    state: &mut SyncState,
+) -> Result<bool> {
+    let dir = get_store_path(app)?;
+    let local = bundle::read_local(&dir)?;
+    let remote_meta = backend.metadata(bundle::DATA_FILE).await?;
+    let last_version = last_sync.and_then(|s| s.data_version.clone());
+    let last_hash = last_sync.and_then(|s| s.data_hash.clone());
+    if local.is_empty() && remote_meta.is_none() {
+        return Ok(false);
+    }
+
+    let (files, remote_hash) = match &remote_meta {
+        Some(meta) if Some(&meta.version) != last_version.as_ref() => {
+            let remote = bundle::unpack(&backend.get(bundle::DATA_FILE).await?)?;
+            let merged = bundle::merge(&local, &remote)?;
+            for (name, data) in &merged {
+                if local.get(name) != Some(data) {
+                    fs::write(dir.join(name), data)?;
+                }
+            }
+            (merged, Some(bundle::hash(&remote)))
+        }
+        // 远端没变，内容就是上次同步后的
+        Some(_) => (local, last_hash.clone()),
+        None => (local, None),
+    };
+    let files_hash = bundle::hash(&files);
+    let version = match remote_meta {
+        Some(meta) if remote_hash.as_ref() == Some(&files_hash) => meta.version,
+        _ => {
+            backend
+                .put(bundle::DATA_FILE, bundle::pack(&files)?)
+                .await?
+                .version
+        }
+    };
+
+    let changed = last_version.as_ref() != Some(&version) || last_hash != Some(files_hash.clone());
+    state.data_version = Some(version);
+    state.data_hash = Some(files_hash);
+    Ok(changed)
+}
+
+// 配置修改后调用，开了自动同步才会真正同步
+pub fn schedule_sync(app: &tauri::AppHandle) {
+    let generation = SYNC_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(SYNC_DELAY_SECS)).await;
+        // 等待期间又改了配置，交给后面那次
+        if SYNC_GENERATION.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let auto_sync = app
+            .state::<AppState>()
+            .setting
+            .read()
+            .await
+            .auto_sync
+            .unwrap_or(false);
+        // 失败时 sync 会发 SyncFinished 事件，由前端提示
+        if auto_sync {
+            let _ = sync(&app, true).await;
+        }
+    });
+}
+
+// 启动时还没有加载配置，先读一遍
+pub async fn sync_on_startup(app: tauri::AppHandle) {
+    let Ok(setting) = Setting::load_setting(&app) else {
+        return;
+    };
+    if !setting.auto_sync.unwrap_or(false) {
+        return;
+    }
+    *app.state::<AppState>().setting.write().await = setting;
+    let _ = sync(&app, true).await;
+}
+
+#[tauri::command(async)]
+pub async fn sync_setting(app: tauri::AppHandle) -> Result<String, SyncError> {
+    let report = sync(&app, false).await?;
+    serde_json::to_string(&report).map_err(|e| SyncError::Other(e.to_string()))
+}
+
+#[tauri::command(async)]
+pub async fn set_sync_backend(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    backend: SyncBackendConfig,
+) -> Result<(), String> {
+    app_state.setting.write().await.sync_backend = Some(backend);
+    app_state
+        .setting
+        .read()
+        .await
+        .write_setting(&app)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command(async)]
+pub async fn set_auto_sync(
+    app: tauri::AppHandle,
+    app_state: tauri::State<'_, AppState>,
+    auto_sync: bool,
+) -> Result<(), String> {
+    app_state.setting.write().await.set_auto_sync(auto_sync);
+    app_state
+        .setting
+        .read()
+        .await
+        .write_setting(&app)
+        .map_err(|e| e.to_string())?;
+    // 刚打开自动同步时先同步一次
+    if auto_sync {
+        schedule_sync(&app);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setting::account::SavedAccount;
+
+    #[test]
+    fn test_decide_direction() {
+        let last = SyncState {
+            backend: "onedrive".to_string(),
+            remote_version: "2025-03-01T08:00:00Z".to_string(),
+            local_hash: "aaa".to_string(),
+            base: None,
+            data_version: None,
+            data_hash: None,
+        };
+        assert_eq!(
+            decide_direction(None, "aaa", Some(&last)),
+            SyncDirection::Upload
+        );
+        assert_eq!(
+            decide_direction(Some("2025-03-01T08:00:00Z"), "aaa", None),
+            SyncDirection::Merge
+        );
+        assert_eq!(
+            decide_direction(Some("2025-03-01T08:00:00Z"), "aaa", Some(&last)),
+            SyncDirection::UpToDate
+        );
+        assert_eq!(
+            decide_direction(Some("2025-03-01T08:00:00Z"), "bbb", Some(&last)),
+            SyncDirection::Upload
+        );
+        assert_eq!(
+            decide_direction(Some("2025-03-03T08:00:00Z"), "aaa", Some(&last)),
+            SyncDirection::Merge
+        );
+        assert_eq!(
+            decide_direction(Some("2025-03-03T08:00:00Z"), "bbb", Some(&last)),
+            SyncDirection::Merge
+        );
+    }
+
+    #[test]
+    fn test_encode_setting() {
+        let setting = Setting {
+            account: vec![SavedAccount::new("u123".to_string(), "p".to_string())],
+            ..Default::default()
+        };
+        let res = decode_setting(&encode_setting(&setting).unwrap()).unwrap();
+        assert_eq!(res.account, setting.account);
+
+        // 只和当前设备有关的设置不上传，也不影响是否有修改
+        let mut local_only = Setting {
+            sync_backend: Some(SyncBackendConfig::WebDav {
+                url: "https://dav.example.com/".to_string(),
+                username: "u".to_string(),
+                password: "secret".to_string(),
+            }),
+            login_interface: Some("en0".to_string()),
+            background_blur: Some(10),
+            ..setting.clone()
+        };
+        local_only.kick_policy.enabled = true;
+        let encoded = URL_SAFE
+            .decode(encode_setting(&local_only).unwrap())
+            .unwrap();
+        let encoded = String::from_utf8(encoded).unwrap();
+        for field in [
+            "sync_backend",
+            "secret",
+            "kick_policy",
+            "report_schedule",
+            "login_interface",
+            "background",
+        ] {
+            assert!(!encoded.contains(field), "{field}");
+        }
+        assert_eq!(
+            setting_hash(&local_only).unwrap(),
+            setting_hash(&setting).unwrap()
+        );
+        let err = SyncError::from(decode_setting(b"{\"error\":{}}").unwrap_err());
+        assert_eq!(err.kind(), "invalidData");
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use super::SyncError;
+use crate::{electric_bill::merge_series, entities::RemainingElectricity};
+
+pub const DATA_FILE: &str = "data.zip";
+const MANIFEST_FILE: &str = "manifest.json";
+// 压缩包格式有变化时加一，旧版本读到新格式会报错而不是乱合并
+const BUNDLE_VERSION: u32 = 1;
+
+// 文件名 -> 内容，按文件名排序，保证同样的数据打包结果一样
+pub type DataFiles = BTreeMap<String, Vec<u8>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    files: Vec<String>,
+}
+
+// 目前只同步电费记录，文件名是电表号
+fn is_data_file(name: &str) -> bool {
+    name.strip_suffix(".json")
+        .is_some_and(|stem| !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit()))
+}
+
+pub fn read_local(dir: &Path) -> Result<DataFiles> {
+    let mut files = DataFiles::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if is_data_file(&name) && entry.file_type()?.is_file() {
+            files.insert(name, fs::read(entry.path())?);
+        }
+    }
+    Ok(files)
+}
+
+pub fn pack(files: &DataFiles) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    // 固定修改时间，内容不变时打包出来的文件也不变
+    let options = SimpleFileOptions::default().last_modified_time(zip::DateTime::default());
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(&serde_json::to_vec(&Manifest {
+        version: BUNDLE_VERSION,
+        files: files.keys().cloned().collect(),
+    })?)?;
+    for (name, data) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+pub fn unpack(data: &[u8]) -> Result<DataFiles> {
+    let invalid =
+        |e: &dyn std::fmt::Display| SyncError::InvalidData(format!("数据压缩包损坏：{e}"));
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(|e| invalid(&e))?;
+    let manifest: Manifest = {
+        let file = zip.by_name(MANIFEST_FILE).map_err(|e| invalid(&e))?;
+        serde_json::from_reader(file).map_err(|e| invalid(&e))?
+    };
+    if manifest.version > BUNDLE_VERSION {
+        return Err(
+            SyncError::InvalidData("远端的数据是新版本上传的，请先更新软件".to_string()).into(),
+        );
+    }
+
+    let mut files = DataFiles::new();
+    for name in manifest.files {
+        // 不信任压缩包里的路径
+        if !is_data_file(&name) {
+            continue;
+        }
+        let mut buf = vec![];
+        zip.by_name(&name)
+            .map_err(|e| invalid(&e))?
+            .read_to_end(&mut buf)?;
+        files.insert(name, buf);
+    }
+    Ok(files)
+}
+
+// 解析失败说明文件已经损坏，直接报错，不能当成空记录合并后覆盖掉另一边
+fn parse_series(name: &str, data: &[u8]) -> Result<Vec<RemainingElectricity>> {
+    serde_json::from_slice(data)
+        .map_err(|e| SyncError::InvalidData(format!("电费记录 {name} 已损坏：{e}")).into())
+}
+
+// 两边都有的电表按时间合并记录，只有一边有的直接保留
+pub fn merge(local: &DataFiles, remote: &DataFiles) -> Result<DataFiles> {
+    let mut res = local.clone();
+    for (name, remote_data) in remote {
+        let data = match local.get(name) {
+            Some(local_data) if local_data == remote_data => continue,
+            Some(local_data) => serde_json::to_vec(&merge_series(
+                &parse_series(name, local_data)?,
+                &parse_series(name, remote_data)?,
+            ))?,
+            None => remote_data.clone(),
+        };
+        res.insert(name.clone(), data);
+    }
+    Ok(res)
+}
+
+pub fn hash(files: &DataFiles) -> String {
+    let mut hasher = Sha256::new();
+    for (name, data) in files {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(items: &[(i64, i32)]) -> Vec<u8> {
+        let items: Vec<_> = items
+            .iter()
+            .map(|&(date, remain)| RemainingElectricity {
+                date,
+                remain,
+                average: 0.0,
+            })
+            .collect();
+        serde_json::to_vec(&items).unwrap()
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let files = DataFiles::from([
+            ("123.json".to_string(), series(&[(1_740_800_000, 100)])),
+            ("456.json".to_string(), b"[]".to_vec()),
+        ]);
+        let packed = pack(&files).unwrap();
+        assert_eq!(unpack(&packed).unwrap(), files);
+        assert_eq!(pack(&files).unwrap(), packed);
+
+        let err = SyncError::from(unpack(b"not a zip").unwrap_err());
+        assert_eq!(err.kind(), "invalidData");
+        assert!(is_data_file("123.json"));
+        assert!(!is_data_file("../123.json"));
+        assert!(!is_data_file(".json"));
+    }
+
+    #[test]
+    fn test_merge() {
+        const DAY: i64 = 24 * 60 * 60;
+        let start = 1_740_800_000;
+        let local = DataFiles::from([
+            ("1.json".to_string(), series(&[(start, 100)])),
+            ("2.json".to_string(), series(&[(start, 50)])),
+        ]);
+        let remote = DataFiles::from([
+            ("1.json".to_string(), series(&[(start + DAY, 90)])),
+            ("3.json".to_string(), series(&[(start, 10)])),
+        ]);
+        let res = merge(&local, &remote).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["2.json"], local["2.json"]);
+        assert_eq!(res["3.json"], remote["3.json"]);
+        let merged = parse_series("1.json", &res["1.json"]).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[1].average, 10.0);
+        assert_ne!(hash(&res), hash(&local));
+
+        // 任何一边的记录损坏都要中止合并，不能把好的数据覆盖成空
+        let corrupt = DataFiles::from([("1.json".to_string(), b"{not json".to_vec())]);
+        let err = SyncError::from(merge(&local, &corrupt).unwrap_err());
+        assert_eq!(err.kind(), "invalidData");
+        let err = SyncError::from(merge(&corrupt, &remote).unwrap_err());
+        assert_eq!(err.kind(), "invalidData");
+    }
+}
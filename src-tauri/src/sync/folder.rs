@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::fs;
+
+use super::{RemoteMeta, SyncBackend};
+
+// 同步到本地文件夹，可以是网盘客户端的同步目录或者挂载的共享文件夹
+pub struct FolderBackend {
+    dir: PathBuf,
+}
+
+impl FolderBackend {
+    pub fn new(dir: &str) -> Self {
+        FolderBackend {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+impl SyncBackend for FolderBackend {
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>> {
+        let metadata = match fs::metadata(self.dir.join(name)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let modified = DateTime::<Utc>::from(metadata.modified()?);
+        Ok(Some(RemoteMeta {
+            // 精确到纳秒，再带上大小，避免同一秒内的修改认不出来
+            version: format!(
+                "{}/{}",
+                modified.to_rfc3339_opts(SecondsFormat::Nanos, true),
+                metadata.len()
+            ),
+            modified: Some(modified),
+        }))
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.dir.join(name)).await?)
+    }
+
+    // 先写临时文件再改名，别的设备不会读到写了一半的文件
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteMeta> {
+        if !fs::try_exists(&self.dir).await? {
+            return Err(anyhow!("同步文件夹不存在：{}", self.dir.display()));
+        }
+        let tmp = self.dir.join(format!(".{name}.tmp"));
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, self.dir.join(name)).await?;
+        self.metadata(name)
+            .await?
+            .ok_or(anyhow!("写入后找不到文件：{name}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_folder_backend() {
+        let dir = std::env::temp_dir().join(format!("ustb-sync-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backend = FolderBackend::new(dir.to_str().unwrap());
+
+        assert_eq!(backend.metadata("setting.txt").await.unwrap(), None);
+        let first = backend.put("setting.txt", b"abc".to_vec()).await.unwrap();
+        assert_eq!(backend.get("setting.txt").await.unwrap(), b"abc");
+        assert_eq!(
+            backend.metadata("setting.txt").await.unwrap(),
+            Some(first.clone())
+        );
+        let second = backend.put("setting.txt", b"abcd".to_vec()).await.unwrap();
+        assert_ne!(first.version, second.version);
+
+        assert!(
+            FolderBackend::new(dir.join("missing").to_str().unwrap())
+                .put("setting.txt", vec![])
+                .await
+                .is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::LazyLock;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use reqwest::{Method, RequestBuilder, StatusCode, Url};
+
+use super::{RemoteMeta, SyncBackend, SyncError};
+use crate::requests::CLIENT;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:getlastmodified/></d:prop></d:propfind>"#;
+
+// 不同服务器的命名空间前缀不一样，比如 d:、D: 或者没有
+static ETAG_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?getetag[^>]*>(.*?)</(?:\w+:)?getetag>").unwrap());
+static LAST_MODIFIED_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<(?:\w+:)?getlastmodified[^>]*>(.*?)</(?:\w+:)?getlastmodified>").unwrap()
+});
+
+// 通用 WebDAV，比如坚果云：https://dav.jianguoyun.com/dav/ustb-wifi-tools/
+pub struct WebDavBackend {
+    dir: Url,
+    username: String,
+    password: String,
+}
+
+impl WebDavBackend {
+    pub fn new(url: &str, username: &str, password: &str) -> Result<Self> {
+        let mut dir = Url::parse(url.trim())?;
+        if !matches!(dir.scheme(), "http" | "https") {
+            return Err(anyhow!("WebDAV 地址必须以 http:// 或 https:// 开头"));
+        }
+        // 当作文件夹，不然 join 会把最后一段替换掉
+        if !dir.path().ends_with('/') {
+            dir.set_path(&format!("{}/", dir.path()));
+        }
+        Ok(WebDavBackend {
+            dir,
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        CLIENT
+            .request(method, url)
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    fn file_url(&self, name: &str) -> Result<Url> {
+        Ok(self.dir.join(name)?)
+    }
+}
+
+fn check_status(status: StatusCode) -> Result<()> {
+    let err = match status {
+        s if s.is_success() => return Ok(()),
+        StatusCode::UNAUTHORIZED => SyncError::Auth("WebDAV 用户名或密码错误".to_string()),
+        StatusCode::INSUFFICIENT_STORAGE => SyncError::QuotaExceeded,
+        s => SyncError::Remote {
+            status: Some(s.as_u16()),
+            code: s.canonical_reason().unwrap_or_default().to_string(),
+            message: "WebDAV 请求失败".to_string(),
+        },
+    };
+    Err(err.into())
+}
+
+fn parse_propfind(xml: &str) -> Result<RemoteMeta> {
+    let capture = |re: &Regex| {
+        re.captures(xml)
+            .map(|cap| cap[1].trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let etag = capture(&ETAG_RE);
+    let last_modified = capture(&LAST_MODIFIED_RE);
+    let modified = last_modified
+        .as_deref()
+        .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+        .map(|t| t.with_timezone(&Utc));
+    Ok(RemoteMeta {
+        version: etag
+            .or(last_modified)
+            .ok_or(anyhow!("WebDAV 服务器没有返回 ETag 和修改时间"))?,
+        modified,
+    })
+}
+
+impl SyncBackend for WebDavBackend {
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteMeta>> {
+        let response = self
+            .request(Method::from_bytes(b"PROPFIND")?, self.file_url(name)?)
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        check_status(response.status())?;
+        Ok(Some(parse_propfind(&response.text().await?)?))
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let response = self
+            .request(Method::GET, self.file_url(name)?)
+            .send()
+            .await?;
+        check_status(response.status())?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteMeta> {
+        let url = self.file_url(name)?;
+        let mut response = self
+            .request(Method::PUT, url.clone())
+            .body(data.clone())
+            .send()
+            .await?;
+        // 文件夹还不存在，先建一个
+        if response.status() == StatusCode::CONFLICT {
+            let mkcol = self
+                .request(Method::from_bytes(b"MKCOL")?, self.dir.clone())
+                .send()
+                .await?;
+            check_status(mkcol.status())?;
+            response = self.request(Method::PUT, url).body(data).send().await?;
+        }
+        check_status(response.status())?;
+        self.metadata(name)
+            .await?
+            .ok_or(anyhow!("上传后找不到文件：{name}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::Mutex,
+    };
+
+    use super::*;
+
+    // 路径 -> (内容, ETag)
+    type Files = Arc<Mutex<HashMap<String, (Vec<u8>, u32)>>>;
+
+    // 最简单的 WebDAV 服务器，文件存在内存里，ETag 是写入次数
+    async fn serve(listener: TcpListener, files: Files) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let files = files.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+                let mut content_length = 0;
+                let mut authorized = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(len) = line.strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                    // user:pass
+                    authorized |= line == "authorization: basic dxnlcjpwyxnz";
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).await.unwrap();
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().to_string();
+                let mut files = files.lock().await;
+                let (status, res_body) = if !authorized {
+                    ("401 Unauthorized", String::new())
+                } else {
+                    match method.as_str() {
+                        "PROPFIND" => match files.get(&path) {
+                            Some((_, etag)) => (
+                                "207 Multi-Status",
+                                format!(
+                                    r#"<D:multistatus xmlns:D="DAV:"><D:response><D:propstat><D:prop><D:getetag>"{etag}"</D:getetag><D:getlastmodified>Sat, 01 Mar 2025 08:00:00 GMT</D:getlastmodified></D:prop></D:propstat></D:response></D:multistatus>"#
+                                ),
+                            ),
+                            None => ("404 Not Found", String::new()),
+                        },
+                        "GET" => match files.get(&path) {
+                            Some((data, _)) => ("200 OK", String::from_utf8(data.clone()).unwrap()),
+                            None => ("404 Not Found", String::new()),
+                        },
+                        "PUT" if !files.contains_key("/dav/sync/") => {
+                            ("409 Conflict", String::new())
+                        }
+                        "PUT" => {
+                            let etag = files.get(&path).map(|(_, e)| e + 1).unwrap_or(1);
+                            files.insert(path, (body, etag));
+                            ("201 Created", String::new())
+                        }
+                        "MKCOL" => {
+                            files.insert(path, (vec![], 0));
+                            ("201 Created", String::new())
+                        }
+                        _ => ("405 Method Not Allowed", String::new()),
+                    }
+                };
+                let res = format!(
+                    "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{res_body}",
+                    res_body.len()
+                );
+                reader.get_mut().write_all(res.as_bytes()).await.unwrap();
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webdav_backend() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dav/sync", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, Arc::default()));
+
+        let backend = WebDavBackend::new(&url, "user", "pass").unwrap();
+        assert_eq!(backend.metadata("setting.txt").await.unwrap(), None);
+        let first = backend.put("setting.txt", b"abc".to_vec()).await.unwrap();
+        assert_eq!(first.version, "\"1\"");
+        assert_eq!(
+            first.modified,
+            DateTime::parse_from_rfc3339("2025-03-01T08:00:00Z")
+                .ok()
+                .map(|t| t.with_timezone(&Utc))
+        );
+        assert_eq!(backend.get("setting.txt").await.unwrap(), b"abc");
+        let second = backend.put("setting.txt", b"abcd".to_vec()).await.unwrap();
+        assert_eq!(second.version, "\"2\"");
+
+        let wrong = WebDavBackend::new(&url, "user", "wrong").unwrap();
+        let err = wrong.get("setting.txt").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SyncError>(),
+            Some(SyncError::Auth(_))
+        ));
+        assert!(WebDavBackend::new("ftp://example.com/", "", "").is_err());
+    }
+
+    #[test]
+    fn test_parse_propfind() {
+        let meta = parse_propfind(
+            r#"<multistatus xmlns="DAV:"><response><propstat><prop><getlastmodified>Sat, 01 Mar 2025 08:00:00 GMT</getlastmodified></prop></propstat></response></multistatus>"#,
+        )
+        .unwrap();
+        assert_eq!(meta.version, "Sat, 01 Mar 2025 08:00:00 GMT");
+        assert!(meta.modified.is_some());
+        assert!(parse_propfind("<multistatus/>").is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    sync::Arc,
+};
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, Timelike};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use tauri::ipc::Channel;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    entities::{OnlineLogEvent, UserType},
+    requests::get_user_online_log,
+    utils::number_or_string,
+};
+
+// 一次请求的条数，一个月的明细一般一页就够
+const PAGE_SIZE: usize = 1000;
+// 同时查询的区间数，太多了校园网后台会拒绝
+pub const MAX_CONCURRENT_QUERIES: usize = 4;
+
+// getUserOnlineLog 返回的一条上网记录，流量单位 MB，时长单位分钟
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OnlineLogRow {
+    pub login_time: i64,  // 毫秒时间戳
+    pub logout_time: i64, // 毫秒时间戳
+    pub mac_address: String,
+    pub user_ip: String,
+    #[serde(deserialize_with = "number_or_string")]
+    pub flow: f64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub internet_down_flow: f64, // IPv4 下行
+    #[serde(deserialize_with = "number_or_string")]
+    pub internet_up_flow: f64, // IPv4 上行
+    #[serde(deserialize_with = "number_or_string")]
+    pub chinanet_down_flow: f64, // IPv6 下行
+    #[serde(deserialize_with = "number_or_string")]
+    pub chinanet_up_flow: f64, // IPv6 上行
+    #[serde(deserialize_with = "number_or_string")]
+    pub cost_money: f64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub time: f64,
+}
+
+impl OnlineLogRow {
+    pub fn ipv4_flow(&self) -> f64 {
+        self.internet_down_flow + self.internet_up_flow
+    }
+
+    pub fn ipv6_flow(&self) -> f64 {
+        self.chinanet_down_flow + self.chinanet_up_flow
+    }
+
+    // 把这次在线按整点切开，返回每段所在小时的开始时间和分钟数
+    pub fn hourly_minutes(&self, offset: &FixedOffset) -> Vec<(DateTime<FixedOffset>, f64)> {
+        let (Some(login), Some(logout)) = (
+            DateTime::from_timestamp_millis(self.login_time),
+            DateTime::from_timestamp_millis(self.logout_time),
+        ) else {
+            return vec![];
+        };
+        let (mut cur, logout) = (login.with_timezone(offset), logout.with_timezone(offset));
+        let mut res = vec![];
+        while cur < logout {
+            let hour = cur
+                .with_minute(0)
+                .and_then(|t| t.with_second(0))
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(cur);
+            let end = (hour + Duration::hours(1)).min(logout);
+            res.push((hour, (end - cur).num_seconds() as f64 / 60.0));
+            cur = end;
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OnlineLog {
+    pub summary: Value, // 汇总，前端直接显示
+    pub rows: Vec<OnlineLogRow>,
+}
+
+pub fn parse_online_log(json_str: &str) -> Result<OnlineLog> {
+    serde_json::from_str(json_str).map_err(|_| anyhow!("获取上网明细失败，可能是登录已失效"))
+}
+
+// 按自然月切开，两头都包含
+pub fn month_chunks(start: NaiveDate, end: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut res = vec![];
+    let mut cur = start;
+    while cur <= end {
+        let chunk_end = month_range(cur.year(), cur.month()).map_or(end, |(_, e)| e.min(end));
+        res.push((cur, chunk_end));
+        match chunk_end.succ_opt() {
+            Some(next) => cur = next,
+            None => break,
+        }
+    }
+    res
+}
+
+// 把不同区间查到的明细合成一份，汇总的数字相加，明细按上线时间倒序
+pub fn merge_online_logs(mut parts: Vec<Value>) -> Value {
+    if parts.len() == 1 {
+        return parts.remove(0);
+    }
+    let mut summary = Map::new();
+    let mut total = 0;
+    let mut rows = vec![];
+    for part in parts {
+        if let Some(part_summary) = part["summary"].as_object() {
+            for (key, value) in part_summary {
+                let sum = summary.get(key).and_then(Value::as_f64).unwrap_or_default()
+                    + value.as_f64().unwrap_or_default();
+                summary.insert(key.clone(), json!(round_to(sum, 3)));
+            }
+        }
+        total += part["total"].as_u64().unwrap_or_default();
+        if let Some(part_rows) = part["rows"].as_array() {
+            rows.extend(part_rows.iter().cloned());
+        }
+    }
+    rows.sort_by_key(|row| Reverse(row["loginTime"].as_i64().unwrap_or_default()));
+    json!({ "summary": summary, "total": total, "rows": rows })
+}
+
+// 查一个区间的全部明细，一页放不下就接着翻页，取不全时报错，不悄悄地只返回一部分
+async fn fetch_range(
+    cookie_str: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+    user_type: &UserType,
+) -> Result<Value> {
+    let (start_date, end_date) = (
+        start.format("%Y-%m-%d").to_string(),
+        end.format("%Y-%m-%d").to_string(),
+    );
+    let mut first_page: Option<Value> = None;
+    let mut rows: Vec<Value> = vec![];
+    for page_number in 1.. {
+        let json_str = get_user_online_log(
+            cookie_str,
+            &start_date,
+            &end_date,
+            page_number,
+            PAGE_SIZE,
+            user_type,
+        )
+        .await?;
+        let mut page: Value = serde_json::from_str(&json_str)
+            .map_err(|_| anyhow!("获取上网明细失败，可能是登录已失效"))?;
+        let page_rows = match page.get_mut("rows").map(Value::take) {
+            Some(Value::Array(page_rows)) => page_rows,
+            _ => vec![],
+        };
+        // 后台不认分页参数的话每一页都一样
+        if page_number > 1 && !page_rows.is_empty() && page_rows.first() == rows.first() {
+            return Err(anyhow!(
+                "校园网后台没有按页返回，{start_date} 到 {end_date} 的上网明细只取到了 {} 条",
+                rows.len()
+            ));
+        }
+        let total = page["total"].as_u64().unwrap_or_default() as usize;
+        let last_page = page_rows.len() < PAGE_SIZE;
+        rows.extend(page_rows);
+        first_page.get_or_insert(page);
+        if rows.len() >= total {
+            break;
+        }
+        if last_page {
+            return Err(anyhow!(
+                "{start_date} 到 {end_date} 的上网明细只取到了 {}/{} 条",
+                rows.len(),
+                total
+            ));
+        }
+    }
+    let mut log = first_page.unwrap_or(json!({}));
+    log["rows"] = Value::Array(rows);
+    Ok(log)
+}
+
+// 按月分段、分页获取上网明细，同时查询几段，合成和校园网后台一样格式的 JSON
+// on_event 不为空时每查完一段报告一次进度
+pub async fn fetch_online_log(
+    cookie_str: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+    user_type: &UserType,
+    on_event: Option<&Channel<OnlineLogEvent>>,
+) -> Result<String> {
+    let chunks = month_chunks(start, end);
+    let send = |event: OnlineLogEvent| {
+        if let Some(on_event) = on_event {
+            let _ = on_event.send(event);
+        }
+    };
+    send(OnlineLogEvent::Started {
+        chunks: chunks.len(),
+    });
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_QUERIES));
+    let mut tasks = JoinSet::new();
+    for &(start, end) in &chunks {
+        let (cookie_str, user_type) = (cookie_str.to_string(), user_type.clone());
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            fetch_range(&cookie_str, start, end, &user_type).await
+        });
+    }
+    let mut parts = vec![];
+    let mut rows = 0;
+    while let Some(res) = tasks.join_next().await {
+        let part = res??;
+        rows += part["rows"].as_array().map_or(0, Vec::len);
+        parts.push(part);
+        send(OnlineLogEvent::Progress {
+            finished_chunks: parts.len(),
+            chunks: chunks.len(),
+            rows,
+        });
+    }
+    Ok(merge_online_logs(parts).to_string())
+}
+
+// getMonthPay 的一行是数组：
+// [开始时间, 结束时间, 用户类型, 基本费用, 实际花费, 时长(分钟), 流量(MB), 更新时间]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthBill {
+    pub start_time: i64, // 毫秒时间戳
+    pub used_money: f64,
+    pub used_time: f64,
+    pub used_flow: f64,
+}
+
+impl MonthBill {
+    fn from_row(row: &[Value]) -> Option<Self> {
+        let num = |i: usize| row.get(i).and_then(Value::as_f64);
+        Some(MonthBill {
+            start_time: num(0)? as i64,
+            used_money: num(4).unwrap_or_default(),
+            used_time: num(5).unwrap_or_default(),
+            used_flow: num(6).unwrap_or_default(),
+        })
+    }
+
+    // 账单的开始时间是当月一号零点
+    pub fn month(&self, offset: &FixedOffset) -> Option<(i32, u32)> {
+        let date = DateTime::from_timestamp_millis(self.start_time)?.with_timezone(offset);
+        Some((date.year(), date.month()))
+    }
+}
+
+fn month_bills(month_pay: &Value) -> Vec<MonthBill> {
+    month_pay["rows"]
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .filter_map(|row| MonthBill::from_row(row.as_array()?))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn parse_month_pay(json_str: &str) -> Result<Vec<MonthBill>> {
+    let value: Value = serde_json::from_str(json_str)
+        .map_err(|_| anyhow!("获取月度账单失败，可能是登录已失效"))?;
+    Ok(month_bills(&value))
+}
+
+// 某个月的第一天和最后一天
+pub fn month_range(year: i32, month: u32) -> Option<(NaiveDate, NaiveDate)> {
+    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let end = start.checked_add_months(Months::new(1))?.pred_opt()?;
+    Some((start, end))
+}
+
+fn day_start_millis(date: NaiveDate, offset: &FixedOffset) -> Option<i64> {
+    Some(
+        date.and_hms_opt(0, 0, 0)?
+            .and_local_timezone(*offset)
+            .single()?
+            .timestamp_millis(),
+    )
+}
+
+fn round_to(value: f64, digits: i32) -> f64 {
+    let scale = 10f64.powi(digits);
+    (value * scale).round() / scale
+}
+
+// 年度账单里缺的月份，往年应该有 12 个月，今年只到上个月，这个月的账单月底才出
+pub fn missing_months(
+    month_pay: &Value,
+    year: i32,
+    today: NaiveDate,
+    offset: &FixedOffset,
+) -> Vec<u32> {
+    let last = match year.cmp(&today.year()) {
+        Ordering::Less => 12,
+        Ordering::Equal => today.month() - 1,
+        Ordering::Greater => 0,
+    };
+    let bills = month_bills(month_pay);
+    (1..=last)
+        .filter(|m| !bills.iter().any(|b| b.month(offset) == Some((year, *m))))
+        .collect()
+}
+
+// 用上网明细补上缺的月份，补的行最后多一个 true，汇总也加上补的部分
+// 没有上网明细的月份补不了，放在 missingMonths 里告诉前端
+// 上网明细查询失败的月份放在 failedMonths 里，重新查询可能就有了
+pub fn fill_month_pay(
+    month_pay: &mut Value,
+    year: i32,
+    missing: &[u32],
+    logs: &[(u32, Vec<OnlineLogRow>)],
+    failed: &[u32],
+    offset: &FixedOffset,
+) {
+    let Some(month_pay) = month_pay.as_object_mut() else {
+        return;
+    };
+    let mut rows = month_pay
+        .get("rows")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let mut summary = month_pay.get("summary").cloned().unwrap_or(json!({}));
+    let mut still_missing = vec![];
+    for &month in missing.iter().filter(|m| !failed.contains(m)) {
+        let log = logs
+            .iter()
+            .find(|(m, _)| *m == month)
+            .map(|(_, rows)| rows.as_slice())
+            .unwrap_or_default();
+        let Some((start, end)) = month_range(year, month)
+            .filter(|_| !log.is_empty())
+            .and_then(|(start, end)| {
+                Some((
+                    day_start_millis(start, offset)?,
+                    day_start_millis(end.succ_opt()?, offset)?,
+                ))
+            })
+        else {
+            still_missing.push(month);
+            continue;
+        };
+        let money = round_to(log.iter().map(|r| r.cost_money).sum(), 2);
+        let time = log.iter().map(|r| r.time).sum::<f64>();
+        let flow = round_to(log.iter().map(|r| r.flow).sum(), 3);
+        rows.push(json!([
+            start,
+            end,
+            "",
+            0.0,
+            money,
+            time,
+            flow,
+            Value::Null,
+            true
+        ]));
+        for (key, value) in [("USEDMONEY", money), ("USETIME", time), ("USEFLOW", flow)] {
+            let digits = if key == "USEDMONEY" { 2 } else { 3 };
+            summary[key] = json!(round_to(
+                summary[key].as_f64().unwrap_or_default() + value,
+                digits
+            ));
+        }
+    }
+    rows.sort_by_key(|row| row[0].as_i64().unwrap_or_default());
+    month_pay.insert("total".to_string(), json!(rows.len()));
+    month_pay.insert("rows".to_string(), Value::Array(rows));
+    month_pay.insert("summary".to_string(), summary);
+    month_pay.insert("missingMonths".to_string(), json!(still_missing));
+    let mut failed = failed.to_vec();
+    failed.sort();
+    month_pay.insert("failedMonths".to_string(), json!(failed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hourly_minutes() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        // 北京时间 2025-03-01 22:30 到 2025-03-02 00:15
+        let row = OnlineLogRow {
+            login_time: 1740839400000,
+            logout_time: 1740845700000,
+            ..Default::default()
+        };
+        let res: Vec<_> = row
+            .hourly_minutes(&offset)
+            .iter()
+            .map(|(t, m)| (t.format("%d %H").to_string(), *m))
+            .collect();
+        assert_eq!(
+            res,
+            [
+                ("01 22".to_string(), 30.0),
+                ("01 23".to_string(), 60.0),
+                ("02 00".to_string(), 15.0)
+            ]
+        );
+        // 还没下线的记录下线时间是 0
+        let row = OnlineLogRow {
+            logout_time: 0,
+            ..row
+        };
+        assert!(row.hourly_minutes(&offset).is_empty());
+    }
+
+    #[test]
+    fn test_month_chunks() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(
+            month_chunks(date("2025-11-15"), date("2026-02-03")),
+            [
+                (date("2025-11-15"), date("2025-11-30")),
+                (date("2025-12-01"), date("2025-12-31")),
+                (date("2026-01-01"), date("2026-01-31")),
+                (date("2026-02-01"), date("2026-02-03")),
+            ]
+        );
+        assert_eq!(
+            month_chunks(date("2026-02-03"), date("2026-02-03")),
+            [(date("2026-02-03"), date("2026-02-03"))]
+        );
+        assert!(month_chunks(date("2026-02-04"), date("2026-02-03")).is_empty());
+    }
+
+    #[test]
+    fn test_merge_online_logs() {
+        let part = |login_times: &[i64], flow: f64| {
+            json!({
+                "summary": {"FLOW": flow, "COU": login_times.len()},
+                "total": login_times.len(),
+                "rows": login_times.iter().map(|t| json!({"loginTime": t})).collect::<Vec<_>>(),
+            })
+        };
+        let merged = merge_online_logs(vec![part(&[3, 1], 0.1), part(&[4, 2], 0.2)]);
+        assert_eq!(merged["summary"], json!({"FLOW": 0.3, "COU": 4.0}));
+        assert_eq!(merged["total"], 4);
+        let login_times: Vec<_> = merged["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["loginTime"].as_i64().unwrap())
+            .collect();
+        assert_eq!(login_times, [4, 3, 2, 1]);
+        assert_eq!(parse_online_log(&merged.to_string()).unwrap().rows.len(), 4);
+    }
+
+    #[test]
+    fn test_fill_month_pay() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        // 2025 年只有 1、2、4 月的账单
+        let mut month_pay = json!({
+            "summary": {"USETIME": 300.0, "USEBASEMONEY": 0.0, "USEFLOW": 3000.0, "USEDMONEY": 1.5},
+            "total": 3,
+            "rows": [
+                [1735660800000i64, 1738339200000i64, "", 0.0, 0.5, 100.0, 1000.0, 1738339200000i64],
+                [1738339200000i64, 1740758400000i64, "", 0.0, 0.5, 100.0, 1000.0, 1740758400000i64],
+                [1743436800000i64, 1746028800000i64, "", 0.0, 0.5, 100.0, 1000.0, 1746028800000i64],
+            ],
+        });
+        let today = NaiveDate::from_ymd_opt(2025, 6, 15).unwrap();
+        let missing = missing_months(&month_pay, 2025, today, &offset);
+        assert_eq!(missing, [3, 5]);
+
+        let log = |flow: f64| OnlineLogRow {
+            flow,
+            cost_money: 0.1,
+            time: 60.0,
+            ..Default::default()
+        };
+        let logs = [(3, vec![log(100.0), log(200.5)]), (5, vec![])];
+        fill_month_pay(&mut month_pay, 2025, &missing, &logs, &[], &offset);
+
+        let rows = month_pay["rows"].as_array().unwrap();
+        assert_eq!(month_pay["total"], 4);
+        // 2025-03-01 00:00 到 2025-04-01 00:00，按时间排在 2 月后面
+        assert_eq!(
+            rows[2],
+            json!([
+                1740758400000i64,
+                1743436800000i64,
+                "",
+                0.0,
+                0.2,
+                120.0,
+                300.5,
+                null,
+                true
+            ])
+        );
+        assert_eq!(rows[3][8], Value::Null);
+        assert_eq!(month_pay["summary"]["USEDMONEY"], 1.7);
+        assert_eq!(month_pay["summary"]["USEFLOW"], 3300.5);
+        assert_eq!(month_pay["missingMonths"], json!([5]));
+        assert_eq!(month_pay["failedMonths"], json!([]));
+        assert_eq!(parse_month_pay(&month_pay.to_string()).unwrap().len(), 4);
+
+        // 查询失败的月份不算没有明细
+        let mut month_pay = json!({"rows": []});
+        fill_month_pay(&mut month_pay, 2025, &[3, 5], &logs[1..], &[3], &offset);
+        assert_eq!(month_pay["missingMonths"], json!([5]));
+        assert_eq!(month_pay["failedMonths"], json!([3]));
+        assert_eq!(month_pay["total"], 0);
+    }
+}
@@ -1,11 +1,27 @@
+pub mod interfaces;
+pub mod ip_location;
+pub mod oui;
 pub mod webvpn;
 
-use anyhow::{anyhow, Result};
-use std::{fs::create_dir, path::PathBuf};
-use tauri::{ipc::Channel, Manager};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::{
+    fs::{OpenOptions, create_dir},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tauri::{Manager, ipc::Channel};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 use crate::{
-    entities::{AppState, DownloadEvent},
+    entities::{AppState, DownloadEvent, UserType},
+    usage::{
+        MAX_CONCURRENT_QUERIES, fetch_online_log, fill_month_pay, missing_months, month_range,
+        parse_online_log,
+    },
 };
 
 #[cfg(target_os = "windows")]
@@ -21,6 +37,44 @@ pub fn get_config_path(app: &tauri::AppHandle) -> Result<PathBuf> {
     Ok(path)
 }
 
+pub fn get_vpn_session_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    let mut path = get_store_path(app)?;
+    path.push("webvpn_session.json");
+    Ok(path)
+}
+
+pub fn get_onedrive_token_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    let mut path = get_store_path(app)?;
+    path.push("onedrive_token.json");
+    Ok(path)
+}
+
+pub fn get_sync_state_path(app: &tauri::AppHandle) -> Result<PathBuf> {
+    let mut path = get_store_path(app)?;
+    path.push("sync_state.json");
+    Ok(path)
+}
+
+// 写入只有当前用户能读的文件，用来存凭据
+pub fn write_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // 以前用 fs::write 写过的文件权限不对，mode 只在创建时生效，这里再改一次
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(data)?;
+    Ok(())
+}
+
 pub fn get_store_path(app: &tauri::AppHandle) -> Result<PathBuf> {
     match app.path().data_dir() {
         Ok(mut p) => {
@@ -35,6 +89,34 @@ pub fn get_store_path(app: &tauri::AppHandle) -> Result<PathBuf> {
     }
 }
 
+// 校园网后台返回的数字有时候是字符串
+pub fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.as_f64().unwrap_or_default(),
+        Value::String(s) => s.trim().parse().unwrap_or_default(),
+        _ => 0.0,
+    })
+}
+
+// 校园网后台和电表都按北京时间记账，日期一律按北京时间算，不跟着系统时区走
+pub fn campus_offset() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).unwrap()
+}
+
+pub fn campus_today() -> NaiveDate {
+    Utc::now().with_timezone(&campus_offset()).date_naive()
+}
+
+// 时间戳（秒）是北京时间的哪一天
+pub fn campus_date(timestamp: i64) -> Option<NaiveDate> {
+    DateTime::from_timestamp(timestamp, 0).map(|t| t.with_timezone(&campus_offset()).date_naive())
+}
+
+// 前端传过来的日期，格式 2024-05-01
+pub fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| anyhow!("日期格式不对: {date}"))
+}
+
 #[inline]
 pub async fn get_cookie_str(app_state: &tauri::State<'_, AppState>) -> Result<String, String> {
     app_state
@@ -45,108 +127,53 @@ pub async fn get_cookie_str(app_state: &tauri::State<'_, AppState>) -> Result<St
         .ok_or("是否已经点击登录校园网后台按钮？".to_string())
 }
 
-// // 用来补全获取校园网后台年度使用数据缺失的数据
-// pub async fn complete_month_pay_data(
-//     month_pay_info: &mut MonthPayInfo,
-//     year: u16,
-//     cookie_str: &str,
-//     user_type: UserType,
-// ) {
-//     // 如果 year 大于 今年，手动加载去年 12 月的数据进来
-//     let this_year = Local::now().year() as u16;
-//     if this_year > year {
-//         let start_date = format!("{}-12-01", year);
-//         let end_date = format!("{}-12-31", year);
-//         let dec_data = match get_user_login_log(cookie_str, &start_date, &end_date, user_type).await
-//         {
-//             Ok(Some(v)) => v,
-//             _ => UserLoginLog::default(),
-//         };
-//         month_pay_info.year_cost += dec_data.cost;
-//         month_pay_info.year_used_duration += dec_data.used_duration;
-//         month_pay_info.year_used_flow += dec_data.used_flow;
-//         month_pay_info.monthly_data.push(MonthlyData {
-//             month: 12,
-//             month_cost: dec_data.cost,
-//             month_used_flow: dec_data.used_flow,
-//             month_used_duration: dec_data.used_duration,
-//         });
-//         // 前端应该改成，超过当年 1 月之后，才显示今年数据，否则是去年数据
-//     }
-
-//     // 如果是2023年，手动获取前8个月的数据
-//     if year == 2023 {
-//         month_pay_info.monthly_data.drain(0..8);
-//         let mut handles = vec![];
-//         for i in 0..8 {
-//             let session_id = session_id.to_string();
-//             let month = i + 1;
-//             let handle = tokio::spawn(async move {
-//                 let start_date = format!("2023-{:02}-01", month);
-//                 let end_date = format!("2023-{:02}-31", month);
-//                 (
-//                     month,
-//                     get_user_login_log(&session_id, &start_date, &end_date, user_type).await,
-//                 )
-//             });
-//             handles.push(handle);
-//         }
-//         for handle in handles {
-//             let (month, data) = handle.await.unwrap();
-//             let data = match data {
-//                 Ok(Some(v)) => v,
-//                 _ => UserLoginLog::default(),
-//             };
-//             month_pay_info.monthly_data.insert(
-//                 month - 1,
-//                 MonthlyData {
-//                     month: month as u8,
-//                     month_cost: data.cost,
-//                     month_used_flow: data.used_flow,
-//                     month_used_duration: data.used_duration,
-//                 },
-//             );
-//             month_pay_info.year_cost += data.cost;
-//             month_pay_info.year_used_flow += data.used_flow;
-//         }
-//     }
-//     // 如果是 2022 年，手动获取 6 ～ 11 月数据（前面12月已经获取完了）
-//     else if year == 2022 {
-//         month_pay_info.monthly_data.drain(5..11);
-//         let mut handles = vec![];
-//         for i in 5..11 {
-//             let session_id = session_id.to_string();
-//             let month = i + 1;
-//             let handle = tokio::spawn(async move {
-//                 let start_date = format!("2022-{:02}-01", month);
-//                 let end_date = format!("2022-{:02}-31", month);
-//                 (
-//                     month,
-//                     get_user_login_log(&session_id, &start_date, &end_date, user_type).await,
-//                 )
-//             });
-//             handles.push(handle);
-//         }
-//         for handle in handles {
-//             let (month, data) = handle.await.unwrap();
-//             let data = match data {
-//                 Ok(Some(v)) => v,
-//                 _ => UserLoginLog::default(),
-//             };
-//             month_pay_info.monthly_data.insert(
-//                 month - 1,
-//                 MonthlyData {
-//                     month: month as u8,
-//                     month_cost: data.cost,
-//                     month_used_flow: data.used_flow,
-//                     month_used_duration: data.used_duration,
-//                 },
-//             );
-//             month_pay_info.year_cost += data.cost;
-//             month_pay_info.year_used_flow += data.used_flow;
-//         }
-//     }
-// }
+// 校园网后台的年度账单有时候缺月份（以前是 12 月和 2022、2023 年的一部分）
+// 缺的月份同时去查上网明细补上，补的行会标记出来
+pub async fn complete_month_pay_data(
+    json_str: &str,
+    year: u16,
+    cookie_str: &str,
+    user_type: &UserType,
+) -> Result<String> {
+    let mut month_pay: Value = serde_json::from_str(json_str)
+        .map_err(|_| anyhow!("获取月度账单失败，可能是登录已失效"))?;
+    let offset = campus_offset();
+    let missing = missing_months(&month_pay, year as i32, campus_today(), &offset);
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_QUERIES));
+    let mut tasks = JoinSet::new();
+    for &month in &missing {
+        let Some((start, end)) = month_range(year as i32, month) else {
+            continue;
+        };
+        let (cookie_str, user_type) = (cookie_str.to_string(), user_type.clone());
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let res = fetch_online_log(&cookie_str, start, end, &user_type, None)
+                .await
+                .and_then(|json_str| parse_online_log(&json_str));
+            (month, res)
+        });
+    }
+    let (mut logs, mut failed) = (vec![], vec![]);
+    while let Some(res) = tasks.join_next().await {
+        match res? {
+            (month, Ok(log)) => logs.push((month, log.rows)),
+            // 查询失败的月份单独告诉前端，和没有明细的区分开
+            (month, Err(_)) => failed.push(month),
+        }
+    }
+    fill_month_pay(
+        &mut month_pay,
+        year as i32,
+        &missing,
+        &logs,
+        &failed,
+        &offset,
+    );
+    Ok(month_pay.to_string())
+}
 
 #[cfg(not(any(target_os = "android", target_os = "linux")))]
 pub async fn update(
@@ -245,4 +272,19 @@ mod test {
         let res = get_windows_build_number();
         dbg!(res);
     }
+
+    #[test]
+    fn test_campus_date() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        // 2025-02-28T15:59:59Z 是北京时间 2 月 28 日最后一秒，下一秒就是 3 月 1 日
+        assert_eq!(campus_date(1740758399), Some(date("2025-02-28")));
+        assert_eq!(campus_date(1740758400), Some(date("2025-03-01")));
+        // 跨年
+        assert_eq!(campus_date(1767196799), Some(date("2025-12-31")));
+        assert_eq!(campus_date(1767196800), Some(date("2026-01-01")));
+
+        assert_eq!(parse_date("2024-02-29").unwrap(), date("2024-02-29"));
+        assert!(parse_date("2025-02-29").is_err());
+        assert!(parse_date("1740758400").is_err());
+    }
 }
@@ -0,0 +1,185 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use super::ip_location::{self, CampusArea};
+
+// 本机的一个网卡，同名的地址合在一起
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalInterface {
+    pub name: String,
+    pub addrs: Vec<IpAddr>,
+    pub campus_area: Option<CampusArea>,
+}
+
+// 登录校园网时绑定的地址，IPv4 和 IPv6 分开绑定，只绑 IPv4 的话 IPv6 就登录不上了
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginBinding {
+    // 按网卡名选择时才有，支持的系统上直接绑定网卡，两种地址都从这个网卡发出去
+    pub interface: Option<String>,
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl LocalInterface {
+    fn ipv4(&self) -> Option<Ipv4Addr> {
+        self.addrs.iter().find_map(|addr| match addr {
+            IpAddr::V4(addr) => Some(*addr),
+            IpAddr::V6(_) => None,
+        })
+    }
+
+    // 链路本地地址连不到登录服务器
+    fn ipv6(&self) -> Option<Ipv6Addr> {
+        self.addrs.iter().find_map(|addr| match addr {
+            IpAddr::V6(addr) if addr.segments()[0] & 0xffc0 != 0xfe80 => Some(*addr),
+            _ => None,
+        })
+    }
+
+    // 宿舍区、教学区的网段最可信，10.0.0.0/8 有可能是别的 VPN
+    fn campus_score(&self) -> u8 {
+        match self.campus_area {
+            Some(CampusArea::Dorm | CampusArea::Teaching) => 2,
+            Some(CampusArea::Campus) => 1,
+            _ => 0,
+        }
+    }
+}
+
+pub fn list_interfaces() -> Result<Vec<LocalInterface>> {
+    let mut res: Vec<LocalInterface> = vec![];
+    for iface in if_addrs::get_if_addrs()? {
+        if iface.is_loopback() {
+            continue;
+        }
+        match res.iter_mut().find(|i| i.name == iface.name) {
+            Some(i) => i.addrs.push(iface.ip()),
+            None => res.push(LocalInterface {
+                name: iface.name.clone(),
+                addrs: vec![iface.ip()],
+                campus_area: None,
+            }),
+        }
+    }
+    for iface in res.iter_mut() {
+        // 校园网的 VPN 地址池不是直连校园网
+        iface.campus_area = iface
+            .addrs
+            .iter()
+            .filter_map(|addr| ip_location::lookup(*addr).campus_area)
+            .filter(|area| !matches!(area, CampusArea::Vpn | CampusArea::Cernet))
+            .max_by_key(|area| matches!(area, CampusArea::Dorm | CampusArea::Teaching));
+    }
+    Ok(res)
+}
+
+// 自动检测：在校园网网段里的网卡，一样可信的取第一个，只用来在界面上提示
+pub fn detect_campus_interface(interfaces: &[LocalInterface]) -> Option<&LocalInterface> {
+    interfaces
+        .iter()
+        .filter(|i| i.campus_score() > 0 && i.ipv4().is_some())
+        .rev()
+        .max_by_key(|i| i.campus_score())
+}
+
+// 登录校园网时绑定的地址，selected 可以是网卡名或者本机的某个 IP 地址
+// 没有选择时返回 None，交给系统选择路由，自动检测到的网卡只用来提示
+pub fn pick_login_binding(
+    interfaces: &[LocalInterface],
+    selected: Option<&str>,
+) -> Result<Option<LoginBinding>> {
+    let Some(selected) = selected else {
+        return Ok(None);
+    };
+    let addr = selected.parse::<IpAddr>().ok();
+    let iface = match addr {
+        Some(addr) => interfaces
+            .iter()
+            .find(|i| i.addrs.contains(&addr))
+            .ok_or(anyhow!(
+                "本机没有 IP 地址 {selected}，可能已经断开，请重新选择"
+            ))?,
+        None => interfaces
+            .iter()
+            .find(|i| i.name == selected)
+            .ok_or(anyhow!("找不到网卡 {selected}，可能已经断开，请重新选择"))?,
+    };
+    // 输入的是 IP 地址时，另一种地址用同一个网卡上的
+    let ipv4 = match addr {
+        Some(IpAddr::V4(addr)) => Some(addr),
+        _ => iface.ipv4(),
+    }
+    .ok_or(anyhow!("网卡 {} 没有 IPv4 地址", iface.name))?;
+    let ipv6 = match addr {
+        Some(IpAddr::V6(addr)) => Some(addr),
+        _ => iface.ipv6(),
+    };
+    Ok(Some(LoginBinding {
+        interface: addr.is_none().then(|| iface.name.clone()),
+        ipv4,
+        ipv6,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(name: &str, addrs: &[&str], campus_area: Option<CampusArea>) -> LocalInterface {
+        LocalInterface {
+            name: name.to_string(),
+            addrs: addrs.iter().map(|a| a.parse().unwrap()).collect(),
+            campus_area,
+        }
+    }
+
+    #[test]
+    fn test_pick_login_binding() {
+        let interfaces = [
+            iface("utun3", &["10.8.0.2"], Some(CampusArea::Campus)),
+            iface(
+                "en0",
+                &["fe80::1", "10.24.21.251", "2001:da8:208::2"],
+                Some(CampusArea::Dorm),
+            ),
+            iface("en5", &["192.168.1.2"], None),
+            iface("en6", &["2001:db8::6"], None),
+        ];
+        assert_eq!(
+            detect_campus_interface(&interfaces).map(|i| i.name.as_str()),
+            Some("en0")
+        );
+        // 没有选择时不绑定
+        assert_eq!(pick_login_binding(&interfaces, None).unwrap(), None);
+        assert_eq!(
+            pick_login_binding(&interfaces, Some("en0")).unwrap(),
+            Some(LoginBinding {
+                interface: Some("en0".to_string()),
+                ipv4: "10.24.21.251".parse().unwrap(),
+                ipv6: Some("2001:da8:208::2".parse().unwrap()),
+            })
+        );
+        assert_eq!(
+            pick_login_binding(&interfaces, Some("en5")).unwrap(),
+            Some(LoginBinding {
+                interface: Some("en5".to_string()),
+                ipv4: "192.168.1.2".parse().unwrap(),
+                ipv6: None,
+            })
+        );
+        assert_eq!(
+            pick_login_binding(&interfaces, Some("2001:da8:208::2")).unwrap(),
+            Some(LoginBinding {
+                interface: None,
+                ipv4: "10.24.21.251".parse().unwrap(),
+                ipv6: Some("2001:da8:208::2".parse().unwrap()),
+            })
+        );
+        assert!(pick_login_binding(&interfaces, Some("10.0.0.9")).is_err());
+        assert!(pick_login_binding(&interfaces, Some("eth9")).is_err());
+        assert!(pick_login_binding(&interfaces, Some("en6")).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::{net::IpAddr, sync::LazyLock};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// 离线归属地表，格式见文件头
+const IP_TABLE: &str = include_str!("../../resources/ip_table.csv");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CampusArea {
+    Dorm,
+    Teaching,
+    Vpn,
+    Campus,
+    Cernet,
+}
+
+// 校园网网段，根据登录记录和学校公开信息整理，学校调整网段后需要同步修改
+// 不能把整个 10.0.0.0/8 算进来，家里的路由器和别的 VPN 也常用这个网段
+const CAMPUS_RANGES: &[(&str, CampusArea, &str)] = &[
+    ("10.24.0.0/13", CampusArea::Dorm, "北科 宿舍区"),
+    ("10.32.0.0/12", CampusArea::Teaching, "北科 教学区"),
+    ("10.254.0.0/16", CampusArea::Vpn, "北科 VPN 地址池"),
+    ("202.204.48.0/20", CampusArea::Campus, "北京科技大学"),
+    ("2001:da8:208::/48", CampusArea::Campus, "北京科技大学"),
+    ("2001:da8:ad::/48", CampusArea::Campus, "北京科技大学"),
+    ("2001:da8::/32", CampusArea::Cernet, "CERNET2"),
+    ("58.192.0.0/12", CampusArea::Cernet, "CERNET"),
+    ("59.64.0.0/12", CampusArea::Cernet, "CERNET"),
+    ("121.192.0.0/13", CampusArea::Cernet, "CERNET"),
+    ("202.112.0.0/16", CampusArea::Cernet, "CERNET"),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LocationSource {
+    Campus,
+    Local,
+    Remote,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpLocation {
+    pub ip: String,
+    pub campus_area: Option<CampusArea>,
+    pub country: String,
+    pub province: String,
+    pub city: String,
+    pub isp: String,
+    pub location: String,
+    pub source: LocationSource,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IpNet {
+    v6: bool,
+    addr: u128,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = s.split_once('/').ok_or(anyhow!("网段格式错误：{s}"))?;
+        let prefix: u8 = prefix.parse()?;
+        let (v6, addr) = match addr.parse::<IpAddr>()? {
+            IpAddr::V4(v4) if prefix <= 32 => (false, u32::from(v4) as u128),
+            IpAddr::V6(v6) if prefix <= 128 => (true, u128::from(v6)),
+            _ => return Err(anyhow!("网段前缀长度错误：{s}")),
+        };
+        Ok(IpNet { v6, addr, prefix })
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        let (v6, bits, value) = match ip {
+            IpAddr::V4(v4) => (false, 32, u32::from(v4) as u128),
+            IpAddr::V6(v6) => (true, 128, u128::from(v6)),
+        };
+        if v6 != self.v6 {
+            return false;
+        }
+        let shift = bits - self.prefix as u32;
+        shift >= bits || (value >> shift) == (self.addr >> shift)
+    }
+}
+
+struct TableEntry {
+    net: IpNet,
+    country: String,
+    province: String,
+    city: String,
+    isp: String,
+}
+
+static CAMPUS_TABLE: LazyLock<Vec<(IpNet, CampusArea, &'static str)>> = LazyLock::new(|| {
+    CAMPUS_RANGES
+        .iter()
+        .filter_map(|(net, area, desc)| Some((IpNet::parse(net).ok()?, *area, *desc)))
+        .collect()
+});
+
+static GEO_TABLE: LazyLock<Vec<TableEntry>> = LazyLock::new(|| {
+    IP_TABLE
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut cols = line.split(',').map(|col| col.trim().to_string());
+            Some(TableEntry {
+                net: IpNet::parse(&cols.next()?).ok()?,
+                country: cols.next().unwrap_or_default(),
+                province: cols.next().unwrap_or_default(),
+                city: cols.next().unwrap_or_default(),
+                isp: cols.next().unwrap_or_default(),
+            })
+        })
+        .collect()
+});
+
+// 先匹配校园网网段，再查离线表，均按最长前缀匹配
+pub fn lookup(ip: IpAddr) -> IpLocation {
+    let ip = ip.to_canonical();
+    let mut res = IpLocation {
+        ip: ip.to_string(),
+        ..Default::default()
+    };
+
+    let geo = GEO_TABLE
+        .iter()
+        .filter(|entry| entry.net.contains(ip))
+        .max_by_key(|entry| entry.net.prefix);
+    if let Some(entry) = geo {
+        res.country = entry.country.clone();
+        res.province = entry.province.clone();
+        res.city = entry.city.clone();
+        res.isp = entry.isp.clone();
+        res.location = [&entry.country, &entry.province, &entry.city, &entry.isp]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .fold(Vec::<&str>::new(), |mut acc, s| {
+                // 北京 北京 这种只显示一次
+                if acc.last() != Some(&s.as_str()) {
+                    acc.push(s);
+                }
+                acc
+            })
+            .join(" ");
+        res.source = LocationSource::Local;
+    }
+
+    let campus = CAMPUS_TABLE
+        .iter()
+        .filter(|(net, _, _)| net.contains(ip))
+        .max_by_key(|(net, _, _)| net.prefix);
+    if let Some((_, area, desc)) = campus {
+        res.campus_area = Some(*area);
+        res.location = desc.to_string();
+        if res.isp.is_empty() || res.isp == "局域网" {
+            res.isp = "教育网".to_string();
+        }
+        res.source = LocationSource::Campus;
+    }
+
+    res
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoteResponse {
+    code: i32,
+    data: Option<RemoteData>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct RemoteData {
+    country: String,
+    province: String,
+    city: String,
+    isp: String,
+    location: String,
+}
+
+impl IpLocation {
+    // 用在线接口 (api.mir6.com) 返回的内容补全
+    pub fn merge_remote(&mut self, text: &str) -> Result<()> {
+        let res: RemoteResponse = serde_json::from_str(text)?;
+        let data = res
+            .data
+            .filter(|_| res.code == 200)
+            .ok_or(anyhow!("在线查询失败，返回码 {}", res.code))?;
+        self.country = data.country;
+        self.province = data.province;
+        self.city = data.city;
+        self.isp = data.isp;
+        self.location = data.location;
+        self.source = LocationSource::Remote;
+        Ok(())
+    }
+}
+
+// 给在线列表每一项加上 location 字段
+pub fn annotate_online_list(json_str: &str) -> Result<String> {
+    let mut items: Vec<Value> = serde_json::from_str(json_str)?;
+    for item in items.iter_mut() {
+        let location = item["ip"]
+            .as_str()
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+            .map(|ip| lookup(ip).location)
+            .unwrap_or_default();
+        if let Some(item) = item.as_object_mut() {
+            item.insert("location".to_string(), Value::String(location));
+        }
+    }
+    Ok(serde_json::to_string(&items)?)
+}
+
+// 给登录记录每一行末尾加上归属地，第 3 列是 IP 地址
+pub fn annotate_login_history(json_str: &str) -> Result<String> {
+    let mut rows: Vec<Vec<Value>> = serde_json::from_str(json_str)?;
+    for row in rows.iter_mut() {
+        let location = row
+            .get(2)
+            .and_then(|ip| ip.as_str())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+            .map(|ip| lookup(ip).location)
+            .unwrap_or_default();
+        row.push(Value::String(location));
+    }
+    Ok(serde_json::to_string(&rows)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_campus() {
+        let res = lookup("10.24.21.251".parse().unwrap());
+        assert_eq!(res.campus_area, Some(CampusArea::Dorm));
+        assert_eq!(res.source, LocationSource::Campus);
+        let res = lookup("10.39.179.219".parse().unwrap());
+        assert_eq!(res.campus_area, Some(CampusArea::Teaching));
+        let res = lookup("2001:da8:208::1".parse().unwrap());
+        assert_eq!(res.campus_area, Some(CampusArea::Campus));
+        let res = lookup("2001:da8:1::1".parse().unwrap());
+        assert_eq!(res.campus_area, Some(CampusArea::Cernet));
+        // 家里的局域网不算校园网
+        let res = lookup("10.0.0.2".parse().unwrap());
+        assert_eq!(res.campus_area, None);
+        assert_eq!(res.location, "局域网");
+    }
+
+    #[test]
+    fn test_lookup_table() {
+        let res = lookup("114.114.114.114".parse().unwrap());
+        assert_eq!(res.campus_area, None);
+        assert_eq!(res.location, "中国 江苏 南京 114DNS");
+        let res = lookup("::ffff:223.5.5.5".parse().unwrap());
+        assert_eq!(res.ip, "223.5.5.5");
+        assert_eq!(res.isp, "阿里云");
+        let res = lookup("240e::1".parse().unwrap());
+        assert_eq!(res.isp, "电信");
+        let res = lookup("9.9.9.9".parse().unwrap());
+        assert_eq!(res.source, LocationSource::Unknown);
+    }
+
+    #[test]
+    fn test_annotate_login_history() {
+        let json = r#"[[1,2,"10.24.0.1","aa"],[1,2,"","bb"]]"#;
+        let res: Vec<Vec<Value>> =
+            serde_json::from_str(&annotate_login_history(json).unwrap()).unwrap();
+        assert_eq!(res[0][4], "北科 宿舍区");
+        assert_eq!(res[1][4], "");
+
+        let json = r#"[{"ip":"10.32.0.1","mac":"aa"},{"ip":"","mac":"bb"}]"#;
+        let res: Vec<Value> = serde_json::from_str(&annotate_online_list(json).unwrap()).unwrap();
+        assert_eq!(res[0]["location"], "北科 教学区");
+        assert_eq!(res[0]["mac"], "aa");
+        assert_eq!(res[1]["location"], "");
+    }
+}
@@ -0,0 +1,50 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+// 离线 MAC 厂商表，格式见文件头
+const OUI_TABLE: &str = include_str!("../../resources/oui_table.csv");
+
+static VENDORS: LazyLock<HashMap<String, &'static str>> = LazyLock::new(|| {
+    OUI_TABLE
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (oui, vendor) = line.split_once(',')?;
+            Some((normalize_mac(oui), vendor.trim()))
+        })
+        .collect()
+});
+
+// 统一成不带分隔符的大写，校园网后台和本机拿到的格式不一样
+pub fn normalize_mac(mac: &str) -> String {
+    mac.chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// 第一个字节的次低位是 1 的是本地管理的地址，一般是系统生成的随机 MAC
+pub fn is_randomized(mac: &str) -> bool {
+    u8::from_str_radix(normalize_mac(mac).get(..2).unwrap_or_default(), 16)
+        .is_ok_and(|b| b & 0x02 != 0)
+}
+
+pub fn lookup_vendor(mac: &str) -> Option<&'static str> {
+    let mac = normalize_mac(mac);
+    VENDORS.get(mac.get(..6)?).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_vendor() {
+        assert_eq!(normalize_mac("f0:18:98:aa:bb:cc"), "F01898AABBCC");
+        assert_eq!(lookup_vendor("F0-18-98-AA-BB-CC"), Some("Apple"));
+        assert_eq!(lookup_vendor("B827EB000001"), Some("树莓派"));
+        assert_eq!(lookup_vendor("123456000000"), None);
+        assert_eq!(lookup_vendor(""), None);
+        assert!(is_randomized("DA:A1:19:00:00:00"));
+        assert!(!is_randomized("F01898AABBCC"));
+    }
+}
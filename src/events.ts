@@ -0,0 +1,105 @@
+import { listen, UnlistenFn } from "@tauri-apps/api/event";
+
+export interface KickDecision {
+  sessionId: string;
+  mac: string;
+  ip: string;
+  hostName: string;
+  kick: boolean;
+  reason: string | null;
+  error: string | null;
+}
+
+export interface KickReport {
+  dryRun: boolean;
+  time: number; // 秒
+  decisions: KickDecision[];
+}
+
+export interface ReportGenerated {
+  userName: string;
+  title: string;
+  path: string;
+}
+
+export interface ReportFailed {
+  userName: string;
+  title: string;
+  error: string;
+  attempts: number;
+  gaveUp: boolean; // 这个周期不再自动重试
+}
+
+// 后端的状态变化都通过 app_event 发过来，和 src-tauri/src/events.rs 里的 AppEvent 对应
+export type AppEvent =
+  | {
+    event: "loginStateChanged";
+    data: {
+      userName: string;
+      loggedIn: boolean;
+    };
+  }
+  | {
+    event: "sessionExpired";
+    data: {
+      userName: string;
+    };
+  }
+  | {
+    event: "balanceChanged";
+    data: {
+      userName: string;
+      oldBalance: number | null;
+      balance: number; // 元
+    };
+  }
+  | {
+    event: "newDeviceOnline";
+    data: {
+      userName: string;
+      mac: string;
+      ip: string;
+      name: string | null;
+    };
+  }
+  | {
+    event: "meterReadingUpdated";
+    data: {
+      ammeterNumber: number;
+      remain: number;
+      average: number | null;
+    };
+  }
+  | {
+    event: "syncFinished";
+    data: {
+      message: string;
+      error: string | null;
+      automatic: boolean; // 后台自动同步，手动同步的结果命令里已经返回了
+    };
+  }
+  | {
+    event: "kickReport";
+    data: KickReport;
+  }
+  | {
+    event: "kickFailed";
+    data: {
+      time: number; // 秒
+      error: string;
+    };
+  }
+  | {
+    event: "reportGenerated";
+    data: ReportGenerated;
+  }
+  | {
+    event: "reportFailed";
+    data: ReportFailed;
+  };
+
+export const listen_app_event = (
+  handler: (event: AppEvent) => void,
+): Promise<UnlistenFn> => {
+  return listen<AppEvent>("app_event", (event) => handler(event.payload));
+};
@@ -1,5 +1,6 @@
+import { Channel } from "@tauri-apps/api/core";
 import dayjs from "dayjs";
-import { CSSProperties } from "vue";
+import { CSSProperties, Ref } from "vue";
 export const railStyle = ({
   focused,
   checked,
@@ -42,3 +43,34 @@ export const timestamp_format = (timestamp: number) => {
 export const date_format = (unix: number) => {
   return dayjs.unix(unix).format("YYYY-MM-DD");
 };
+
+type OnlineLogEvent =
+  | {
+    event: "started";
+    data: {
+      chunks: number;
+    };
+  }
+  | {
+    event: "progress";
+    data: {
+      finishedChunks: number;
+      chunks: number;
+      rows: number;
+    };
+  };
+
+// 上网明细按月分段获取，把进度写到 progress 里，只有一段的时候不显示
+export const online_log_channel = (progress: Ref<string>) => {
+  const onEvent = new Channel<OnlineLogEvent>();
+  onEvent.onmessage = (message) => {
+    if (message.data.chunks <= 1) return;
+    if (message.event === "started") {
+      progress.value = `正在获取上网明细：0/${message.data.chunks} 个月`;
+    } else if (message.event === "progress") {
+      progress.value =
+        `正在获取上网明细：${message.data.finishedChunks}/${message.data.chunks} 个月，已取到 ${message.data.rows} 条`;
+    }
+  };
+  return onEvent;
+};
@@ -2,7 +2,12 @@ import { createApp } from "vue";
 import "./styles.css";
 import App from "./App.vue";
 
-import { LineChart, LineSeriesOption } from "echarts/charts";
+import {
+  HeatmapChart,
+  HeatmapSeriesOption,
+  LineChart,
+  LineSeriesOption,
+} from "echarts/charts";
 import {
   GridComponent,
   GridComponentOption,
@@ -28,6 +33,7 @@ echarts.use([
   GridComponent,
   LegendComponent,
   LineChart,
+  HeatmapChart,
   CanvasRenderer,
   UniversalTransition,
   VisualMapComponent,
@@ -40,6 +46,7 @@ export type EChartsOption = echarts.ComposeOption<
   | GridComponentOption
   | LegendComponentOption
   | LineSeriesOption
+  | HeatmapSeriesOption
   | VisualMapComponentOption
 >;
 
@@ -116,3 +116,21 @@ interface UserGroup {
   userMaxTime: number;
   weekConfig: number;
 }
+
+// 配置文件里保存的账号
+export interface SavedAccount {
+  user_name: string;
+  password: string;
+  display_name: string | null;
+  notes: string | null;
+  login_mode: "direct" | "vpn";
+  auto_login: boolean;
+  pinned: number | null; // 置顶顺序，越小越靠前
+  last_used: number;
+  modified: number;
+}
+
+export const account_label = (account: SavedAccount) =>
+  account.display_name
+    ? `${account.display_name}（${account.user_name}）`
+    : account.user_name;